@@ -1,21 +1,25 @@
-use core::{convert::TryInto, marker::PhantomData};
+use core::{convert::TryFrom, marker::PhantomData, mem, time::Duration};
 use ctaphid_dispatch::app::{self as hid, Command as HidCommand, Message};
-use ctaphid_dispatch::command::VendorCommand;
-use apdu_dispatch::{Command, command, response, app as apdu};
+use apdu_dispatch::{Command as ApduCommand, command, response, app as apdu};
 use apdu_dispatch::iso7816::Status;
 use trussed::{
     syscall,
+    types::{Mechanism, Message as TrussedMessage, Signature, Vec},
     Client as TrussedClient,
 };
 
+use crate::config::{self, Setting};
+use crate::counter::{BatchedCounter, Counter};
+use crate::error_log::BatchedErrorLog;
+use crate::update;
+use crate::command::{Command, Error, EXTENDED, LOCKED, REBOOT, RNG, UPDATE, UUID, VERSION};
+
 pub const USER_PRESENCE_TIMEOUT_SECS: u32 = 15;
 
-const UPDATE: VendorCommand = VendorCommand::H51;
-const REBOOT: VendorCommand = VendorCommand::H53;
-const RNG: VendorCommand = VendorCommand::H60;
-const VERSION: VendorCommand = VendorCommand::H61;
-const UUID: VendorCommand = VendorCommand::H62;
-const LOCKED: VendorCommand = VendorCommand::H63;
+/// Prefix of the messages signed under the response signing policy,
+/// followed by the command id and the response, so that the signatures
+/// cannot pass for attestation key signatures over anything else.
+const SIGNED_RESPONSE_CONTEXT: &[u8] = b"solokeys admin-app response\0";
 
 pub trait Reboot {
     /// Reboots the device.
@@ -35,9 +39,357 @@ pub trait Reboot {
     /// does so.
     fn reboot_to_firmware_update_destructive() -> !;
 
+    /// Reboots the device.
+    ///
+    /// Presuming the device has a recovery mode of operation, reboots
+    /// the device into this mode. By default, that is the firmware
+    /// update mode.
+    fn reboot_to_recovery() -> ! {
+        Self::reboot_to_firmware_update()
+    }
+
     /// Is device bootloader locked down?
     /// E.g., is secure boot enabled?
     fn locked() -> bool;
+
+    /// The configured RNG reseed interval, and the uptime at which
+    /// the RNG was last reseeded, if the platform tracks them.
+    fn rng_reseed_info() -> Option<(Duration, Duration)> {
+        None
+    }
+
+    /// Does the RNG pass its health check?
+    fn rng_healthy() -> bool {
+        true
+    }
+
+    /// Reseeds the RNG after a failed health check,
+    /// returning whether it passes the check again.
+    fn reseed_rng() -> bool {
+        false
+    }
+
+    /// Is an attestation key provisioned?
+    fn attestation_key_provisioned() -> bool {
+        false
+    }
+
+    /// The provisioned attestation certificate, if any.
+    fn attestation_certificate() -> Option<TrussedMessage> {
+        None
+    }
+
+    /// Progress of a firmware update the device downloads itself,
+    /// in percent, if one is in progress.
+    fn update_progress() -> Option<u8> {
+        None
+    }
+
+    /// Number of fingerprint templates the platform can store,
+    /// if it has a fingerprint sensor.
+    fn fingerprint_capacity() -> Option<u8> {
+        None
+    }
+
+    /// Number of enrolled fingerprint templates,
+    /// if the platform has a fingerprint sensor.
+    fn enrolled_fingerprints() -> Option<u8> {
+        None
+    }
+
+    /// Rollback index of the secure-boot subsystem, if it has one.
+    fn secure_boot_rollback_index() -> Option<u32> {
+        None
+    }
+
+    /// Estimated instantaneous power consumption in milliwatts,
+    /// if the platform can measure it.
+    fn power_consumption_mw() -> Option<u32> {
+        None
+    }
+
+    /// Heap fragmentation in percent, if the platform tracks it.
+    fn heap_fragmentation() -> Option<u8> {
+        None
+    }
+
+    /// Shows diagnostics (such as temperature and battery level)
+    /// on the display, returning whether the platform has one.
+    fn show_diagnostics() -> bool {
+        false
+    }
+
+    /// Global attestation signature counter, if the platform keeps one.
+    fn attestation_sign_count() -> Option<u32> {
+        None
+    }
+
+    /// Duration of the last firmware signature verification in
+    /// milliseconds, if the platform measured it.
+    fn last_verify_time_ms() -> Option<u32> {
+        None
+    }
+
+    /// Sensitivity of the capacitive touch sensor, from 0 to 255,
+    /// if the platform has one.
+    fn touch_sensitivity() -> Option<u8> {
+        None
+    }
+
+    /// Sets the sensitivity of the capacitive touch sensor,
+    /// returning whether the platform has one.
+    fn set_touch_sensitivity(_sensitivity: u8) -> bool {
+        false
+    }
+
+    /// Has the user presence hardware (button, touch sensor) failed?
+    fn presence_hardware_failed() -> bool {
+        false
+    }
+
+    /// Confirms user presence by a platform-specific alternate
+    /// mechanism, for when the presence hardware failed.
+    fn confirm_presence_alternately() -> bool {
+        false
+    }
+
+    /// Bytes of storage available for largeBlobs,
+    /// if the build supports them.
+    fn large_blob_capacity() -> Option<u32> {
+        None
+    }
+
+    /// Bytes of largeBlob storage in use, if the build supports largeBlobs.
+    fn large_blob_usage() -> Option<u32> {
+        None
+    }
+
+    /// Microseconds per tick of the system timer.
+    fn tick_resolution_us() -> u32 {
+        1000
+    }
+
+    /// Ids of the apps the runner registered with the dispatchers.
+    fn app_ids() -> &'static [u8] {
+        &[]
+    }
+
+    /// Is the registered app with this id enabled?
+    fn app_enabled(_id: u8) -> bool {
+        true
+    }
+
+    /// Signatures the registered app with this id made over the
+    /// device's lifetime, if the app counts them.
+    fn signature_count(_id: u8) -> Option<u64> {
+        None
+    }
+
+    /// Can the platform sign with the attestation key?
+    ///
+    /// Platforms implementing [`Reboot::sign_with_attestation_key`]
+    /// return `true` once the key is provisioned.
+    fn can_sign_with_attestation_key() -> bool {
+        false
+    }
+
+    /// Did the FIDO app's attestation certificate expire,
+    /// by the platform's clock?
+    fn attestation_certificate_expired() -> bool {
+        false
+    }
+
+    /// Signs a message with the attestation key, if the platform can.
+    fn sign_with_attestation_key(_message: &[u8]) -> Option<Signature> {
+        None
+    }
+
+    /// Verifies the checksums of the credentials stored by the app
+    /// with this id, returning the number of corrupt ones, if the
+    /// app supports it.
+    fn corrupt_credentials(_app_id: u8) -> Option<u16> {
+        None
+    }
+
+    /// Number of presence prompts queued by other apps.
+    fn queued_presence_prompts() -> u8 {
+        0
+    }
+
+    /// Bitmap of the burned rollback protection fuses,
+    /// if the platform has them.
+    fn rollback_fuse_state() -> Option<u32> {
+        None
+    }
+
+    /// Is the hardware debug port (SWD, JTAG) locked?
+    fn debug_port_locked() -> bool {
+        false
+    }
+
+    /// Largest firmware image the platform accepts, in bytes,
+    /// if it limits them.
+    fn max_image_size() -> Option<u32> {
+        None
+    }
+
+    /// Estimated cryptographic operations (such as signatures) per
+    /// second, if the platform measured it.
+    fn crypto_ops_per_sec() -> Option<u32> {
+        None
+    }
+
+    /// Communication errors with the secure element,
+    /// if the platform has one.
+    fn se_comm_errors() -> Option<u32> {
+        None
+    }
+
+    /// Health of the flash wear leveling, as a platform-defined
+    /// byte, if the platform tracks it.
+    fn wear_leveling_status() -> Option<u8> {
+        None
+    }
+
+    /// Longest measured interrupt latency in microseconds,
+    /// if the platform measures it.
+    fn max_interrupt_latency_us() -> Option<u32> {
+        None
+    }
+
+    /// Persistent monotonic counter slots still available,
+    /// if the platform has a finite number of them.
+    fn free_counter_slots() -> Option<u32> {
+        None
+    }
+
+    /// Interface holding the runner's dispatch lock (`0` none, `1` CTAPHID,
+    /// `2` contact, `3` contactless), and whether another one is waiting
+    /// for it, if the runner serializes dispatch.
+    fn dispatch_lock_state() -> Option<(u8, bool)> {
+        None
+    }
+
+    /// Version of the bootloader's update protocol,
+    /// if the platform has a bootloader.
+    fn bootloader_protocol_version() -> Option<u16> {
+        None
+    }
+
+    /// Version of the firmware staged for the next update,
+    /// if the platform can tell before rebooting into the update mode.
+    fn update_candidate_version() -> Option<u32> {
+        None
+    }
+
+    /// Number of certificates the PIV app's storage can hold,
+    /// if the build includes it.
+    fn certificate_capacity() -> Option<u8> {
+        None
+    }
+
+    /// Uptime at which the self-tests last ran, if they ran since boot.
+    fn last_self_test() -> Option<Duration> {
+        None
+    }
+
+    /// Fill level of the entropy pool in percent,
+    /// if the platform tracks it.
+    fn entropy_pool_percent() -> Option<u8> {
+        None
+    }
+
+    /// Peak stack usage of the dispatch task in bytes,
+    /// if the platform measures it.
+    fn dispatch_stack_usage() -> Option<u32> {
+        None
+    }
+
+    /// Peak memory allocated while processing the last command,
+    /// in bytes, if the platform measures it.
+    fn peak_command_alloc() -> Option<u32> {
+        None
+    }
+
+    /// USB vendor and product id, and bitmap of the USB interfaces
+    /// (`0x01` CTAPHID, `0x02` CCID) the runner enumerates, if it has USB.
+    fn usb_descriptor_config() -> Option<(u16, u16, u8)> {
+        None
+    }
+
+    /// Drift of the scheduler tick in parts per million,
+    /// if the platform measures it.
+    fn scheduler_drift_ppm() -> Option<i32> {
+        None
+    }
+
+    /// CRC32 of the firmware image, if the platform computes it.
+    fn firmware_crc32() -> Option<u32> {
+        None
+    }
+
+    /// Number of resident credentials expiring within the given time,
+    /// if the FIDO app tracks their expiry.
+    fn credentials_expiring_within(_window: Duration) -> Option<u32> {
+        None
+    }
+
+    /// Does the platform have a hardware TRNG?
+    ///
+    /// Platforms implementing [`Reboot::trng_bytes`] return `true`.
+    fn has_trng() -> bool {
+        false
+    }
+
+    /// Fills the buffer from the hardware TRNG, without conditioning,
+    /// returning whether the platform has one.
+    fn trng_bytes(_buffer: &mut [u8]) -> bool {
+        false
+    }
+
+    /// Version of the runner's dispatchers, if the runner reports it.
+    fn dispatcher_version() -> Option<u32> {
+        None
+    }
+
+    /// Number of power rails the platform can measure.
+    fn rail_count() -> u8 {
+        0
+    }
+
+    /// Voltage of the given power rail in millivolts, if the platform measured it.
+    fn rail_voltage_mv(_rail: u8) -> Option<u16> {
+        None
+    }
+
+    /// Keepalives the CTAPHID dispatcher sent during the last presence
+    /// prompt or long operation, if the runner counts them.
+    fn last_keepalive_count() -> Option<u32> {
+        None
+    }
+
+    /// Bytes written to persistent storage over the device's lifetime,
+    /// if the platform counts them.
+    fn total_flash_bytes_written() -> Option<u64> {
+        None
+    }
+
+    /// Milliseconds since the last crash, if the platform records
+    /// crashes and one was recorded.
+    fn uptime_since_crash_ms() -> Option<u64> {
+        None
+    }
+}
+
+/// How the FIDO app attests new credentials.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Attestation {
+    /// With the attestation certificate.
+    Full,
+    /// With the credential's own key.
+    SelfAttestation,
+    /// Not at all.
+    Refused,
 }
 
 pub struct App<T, R>
@@ -48,6 +400,26 @@ where T: TrussedClient,
     uuid: [u8; 16],
     version: u32,
     boot_interface: PhantomData<R>,
+    transactions: BatchedCounter,
+    response_high_water: u32,
+    commands: u32,
+    errors: u32,
+    last_error: Option<(u8, Error)>,
+    error_log: BatchedErrorLog,
+    settings: [Option<u32>; Setting::ALL.len()],
+    cache_hits: u32,
+    cache_misses: u32,
+    diagnostics_require_presence: bool,
+    update_checked: bool,
+    rp_list_staged_over_nfc: bool,
+}
+
+/// The interface a command was received on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Transport {
+    Hid,
+    Contact,
+    Contactless,
 }
 
 impl<T, R> App<T, R>
@@ -55,15 +427,774 @@ where T: TrussedClient,
       R: Reboot,
 {
     pub fn new(client: T, uuid: [u8; 16], version: u32) -> Self {
-        Self { trussed: client, uuid, version, boot_interface: PhantomData }
+        Self {
+            trussed: client,
+            uuid,
+            version,
+            boot_interface: PhantomData,
+            transactions: BatchedCounter::new(Counter::Transactions),
+            response_high_water: 0,
+            commands: 0,
+            errors: 0,
+            last_error: None,
+            error_log: BatchedErrorLog::new(),
+            settings: [None; Setting::ALL.len()],
+            cache_hits: 0,
+            cache_misses: 0,
+            diagnostics_require_presence: false,
+            update_checked: false,
+            rp_list_staged_over_nfc: false,
+        }
+    }
+
+    /// Requires user presence for commands reading diagnostics
+    /// (counters and error logs).
+    pub fn require_presence_for_diagnostics(mut self) -> Self {
+        self.diagnostics_require_presence = true;
+        self
+    }
+
+    fn requires_presence(&self, command: Command) -> bool {
+        command.requires_presence() || (self.diagnostics_require_presence && command.is_diagnostic())
     }
 
     fn user_present(&mut self) -> bool {
-        let user_present = syscall!(self.trussed.confirm_user_present(USER_PRESENCE_TIMEOUT_SECS * 1000)).result;
-        user_present.is_ok()
+        if u32::from(R::queued_presence_prompts()) >= self.setting(Setting::PresenceQueueSize) {
+            return false;
+        }
+        if R::presence_hardware_failed() {
+            self.setting(Setting::PresenceFailPolicy) == 1 && R::confirm_presence_alternately()
+        } else {
+            syscall!(self.trussed.confirm_user_present(USER_PRESENCE_TIMEOUT_SECS * 1000)).result.is_ok()
+        }
+    }
+
+    /// Reads a setting, for the app it concerns.
+    ///
+    /// Settings are cached after they were first read, as they are
+    /// consulted on most commands and only the admin app writes them.
+    pub fn setting(&mut self, setting: Setting) -> u32 {
+        if let Some(value) = self.settings[setting as usize] {
+            return value;
+        }
+        let value = setting.read(&mut self.trussed);
+        self.settings[setting as usize] = Some(value);
+        value
+    }
+
+    /// Records that the device entered its recovery mode.
+    pub fn record_recovery_attempt(&mut self) {
+        Counter::RecoveryAttempts.increment(&mut self.trussed);
+    }
+
+    /// Records that the platform throttled the device for its temperature.
+    pub fn record_throttle_event(&mut self) {
+        Counter::ThrottleEvents.increment(&mut self.trussed);
+    }
+
+    /// Handles a panic, as the runner's panic handler reports it,
+    /// rebooting the device (into its recovery mode under the panic policy).
+    pub fn handle_panic(&mut self) -> ! {
+        self.error_log.flush(&mut self.trussed);
+        match self.setting(Setting::PanicPolicy) {
+            1 => R::reboot_to_recovery(),
+            _ => R::reboot(),
+        }
+    }
+
+    /// Handles the loss of the NFC field, as reported by the runner.
+    ///
+    /// An enterprise RP id list write left incomplete over NFC is rolled
+    /// back or kept for the host to resume, following the field loss policy.
+    pub fn field_lost(&mut self) {
+        if !mem::take(&mut self.rp_list_staged_over_nfc) {
+            return;
+        }
+        if self.setting(Setting::FieldLossPolicy) == 0 {
+            config::discard_staged_enterprise_rp_list(&mut self.trussed);
+        }
+    }
+
+    /// Counts an update begun before the last boot if it completed,
+    /// once after boot.
+    ///
+    /// Runners call this at boot, so that the count is up to date
+    /// before the first command. Otherwise it runs when the first
+    /// command is dispatched.
+    pub fn check_completed_update(&mut self) {
+        if self.update_checked {
+            return;
+        }
+        self.update_checked = true;
+        if update::completed(&mut self.trussed, self.version) {
+            Counter::SuccessfulUpdates.increment(&mut self.trussed);
+        }
+    }
+
+    /// Reads a setting for the host, keeping the cache statistics.
+    fn read_setting<const N: usize>(&mut self, setting: Setting, response: &mut Vec<u8, N>) {
+        match self.settings[setting as usize] {
+            Some(_) => self.cache_hits = self.cache_hits.saturating_add(1),
+            None => self.cache_misses = self.cache_misses.saturating_add(1),
+        }
+        let value = self.setting(setting);
+        response.extend_from_slice(&setting.encode(value)).ok();
+    }
+
+    fn write_setting(&mut self, setting: Setting, data: &[u8]) -> Result<(), Error> {
+        self.write_bounded_setting(setting, data, u32::MAX)
+    }
+
+    /// Writes a setting whose valid values are also bounded by the platform.
+    fn write_bounded_setting(&mut self, setting: Setting, data: &[u8], max: u32) -> Result<(), Error> {
+        let data = self.check_config_generation(data)?;
+        let value = setting.parse(data)?;
+        if value > max {
+            return Err(Error::InvalidValue);
+        }
+        setting.write(&mut self.trussed, value)?;
+        self.settings[setting as usize] = Some(value);
+        Counter::ConfigGeneration.increment(&mut self.trussed);
+        Ok(())
+    }
+
+    /// How the FIDO app attests new credentials, following the
+    /// expired certificate policy once its certificate expired.
+    pub fn attestation(&mut self) -> Attestation {
+        if !R::attestation_certificate_expired() {
+            return Attestation::Full;
+        }
+        match self.setting(Setting::ExpiredCertPolicy) {
+            1 => Attestation::SelfAttestation,
+            _ => Attestation::Refused,
+        }
+    }
+
+    /// Reads the RP ids enterprise attestation is allowed for, each
+    /// prefixed with its length in bytes, for the FIDO app.
+    pub fn enterprise_rp_list(&mut self) -> TrussedMessage {
+        config::read_enterprise_rp_list(&mut self.trussed)
+    }
+
+    /// Writes a chunk of the enterprise RP id list, prefixed with a byte
+    /// that is `1` for the last chunk and its offset in the list.
+    ///
+    /// Chunks are staged, the chunk at offset 0 starting a new list. The
+    /// last chunk replaces the list the FIDO app reads, once the user
+    /// confirmed their presence and the list is validated.
+    fn write_enterprise_rp_list(&mut self, transport: Transport, data: &[u8]) -> Result<(), Error> {
+        let data = self.check_config_generation(data)?;
+        let (last, offset, chunk) = match data {
+            [last, high, low, chunk @ ..] => (*last == 1, u16::from_be_bytes([*high, *low]) as usize, chunk),
+            _ => return Err(Error::InvalidLength),
+        };
+        let mut list = match offset {
+            0 => TrussedMessage::new(),
+            _ => config::read_staged_enterprise_rp_list(&mut self.trussed),
+        };
+        if offset != list.len() {
+            return Err(Error::InvalidValue);
+        }
+        list.extend_from_slice(chunk).map_err(|_| Error::InvalidLength)?;
+        if last && !self.user_present() {
+            return Err(Error::NotAvailable);
+        }
+        if !last {
+            config::stage_enterprise_rp_list(&mut self.trussed, list)?;
+            self.rp_list_staged_over_nfc = transport == Transport::Contactless;
+            return Ok(());
+        }
+        config::write_enterprise_rp_list(&mut self.trussed, list)?;
+        self.rp_list_staged_over_nfc = false;
+        Counter::ConfigGeneration.increment(&mut self.trussed);
+        Ok(())
+    }
+
+    fn config_generation(&mut self) -> u32 {
+        let generation = Counter::ConfigGeneration.read(&mut self.trussed);
+        u32::try_from(generation).unwrap_or(u32::MAX)
+    }
+
+    /// Under the reject-on-conflict policy, setting writes are prefixed
+    /// with the configuration generation the host based them on, and
+    /// refused if another write happened since.
+    fn check_config_generation<'a>(&mut self, data: &'a [u8]) -> Result<&'a [u8], Error> {
+        if self.setting(Setting::WriteConflictPolicy) == 0 {
+            return Ok(data);
+        }
+        if data.len() < 4 {
+            return Err(Error::InvalidLength);
+        }
+        let (generation, data) = data.split_at(4);
+        if generation != self.config_generation().to_be_bytes() {
+            return Err(Error::NotAvailable);
+        }
+        Ok(data)
+    }
+
+    /// SHA-256 over the attestation key presence, the attestation
+    /// certificate's SHA-256, the UUID, all settings and the
+    /// enterprise RP id list's SHA-256.
+    fn provisioning_checksum<const N: usize>(&mut self, response: &mut Vec<u8, N>) {
+        let mut message = TrussedMessage::new();
+        message.push(R::attestation_key_provisioned() as u8).ok();
+        if let Some(certificate) = R::attestation_certificate() {
+            message.extend_from_slice(&syscall!(self.trussed.hash(Mechanism::Sha256, certificate)).hash).ok();
+        }
+        message.extend_from_slice(&self.uuid).ok();
+        for &setting in Setting::ALL {
+            let value = self.setting(setting);
+            message.extend_from_slice(&setting.encode(value)).ok();
+        }
+        let list = self.enterprise_rp_list();
+        message.extend_from_slice(&syscall!(self.trussed.hash(Mechanism::Sha256, list)).hash).ok();
+        response.extend_from_slice(&syscall!(self.trussed.hash(Mechanism::Sha256, message)).hash).ok();
     }
 
+    /// Under the response signing policy, appends a signature by the
+    /// attestation key over the response to the given command.
+    fn sign_response<const N: usize>(&mut self, command: Command, response: &mut Vec<u8, N>) -> Result<(), Error> {
+        if self.setting(Setting::ResponseSigningPolicy) == 0 {
+            return Ok(());
+        }
+        let mut message = Vec::<u8, N>::new();
+        message.extend_from_slice(SIGNED_RESPONSE_CONTEXT).map_err(|_| Error::NotAvailable)?;
+        message.push(command.into()).map_err(|_| Error::NotAvailable)?;
+        message.extend_from_slice(response).map_err(|_| Error::NotAvailable)?;
+        let signature = R::sign_with_attestation_key(&message).ok_or(Error::NotAvailable)?;
+        response.extend_from_slice(&signature).map_err(|_| Error::NotAvailable)
+    }
+
+    /// Applies the RNG fail action if the RNG fails its health check,
+    /// refusing random output unless the action lets it continue.
+    fn check_rng_health(&mut self) -> Result<(), Error> {
+        if R::rng_healthy() {
+            return Ok(());
+        }
+        match self.setting(Setting::RngFailAction) {
+            // Continue degraded
+            1 => Ok(()),
+            2 if R::reseed_rng() => Ok(()),
+            _ => Err(Error::NotAvailable),
+        }
+    }
+
+    /// Status of APDUs with an unknown instruction or command id.
+    fn unknown_instruction_status(&mut self) -> Status {
+        // Some middleware expects 6A86 rather than 6D00 for unknown instructions.
+        match self.setting(Setting::UnknownInsPolicy) {
+            1 => Status::IncorrectP1OrP2Parameter,
+            _ => Error::UnsupportedCommand.into(),
+        }
+    }
+
+    /// Under the short-response policy, refuses responses that would
+    /// need chaining over NFC.
+    fn check_response_size<const N: usize>(&mut self, transport: Transport, response: &mut Vec<u8, N>) -> Result<(), Error> {
+        if response.len() > self.response_limit::<N>(transport) {
+            response.clear();
+            return Err(Error::InvalidLength);
+        }
+        Ok(())
+    }
+
+    /// Longest response the transport carries: a short APDU's over NFC
+    /// under the short-only chaining policy, the response buffer otherwise.
+    fn response_limit<const N: usize>(&mut self, transport: Transport) -> usize {
+        let short_only = self.setting(Setting::NfcChainingPolicy) == 1;
+        match transport {
+            Transport::Contactless if short_only => 256,
+            _ => N,
+        }
+    }
+
+    /// Executes the command with the given id, keeping count of commands and
+    /// errors, and track of the last error, also in the persistent log.
+    fn dispatch<const N: usize>(&mut self, transport: Transport, id: u8, data: &[u8], response: &mut Vec<u8, N>) -> Result<(), Error> {
+        self.check_completed_update();
+        let result = match Command::try_from(id) {
+            Ok(command) => self.exec(transport, command, data, response)
+                .and_then(|()| self.sign_response(command, response))
+                .and_then(|()| self.check_response_size(transport, response)),
+            // Hosts probing for commands over CTAPHID may prefer an empty
+            // response to an error, so the probe is no error to account for.
+            Err(_) if transport == Transport::Hid && self.setting(Setting::UnknownCommandPolicy) == 1 => Ok(()),
+            Err(error) => Err(error),
+        };
+        self.commands = self.commands.saturating_add(1);
+        match result {
+            Ok(()) => self.last_error = None,
+            Err(error) => {
+                self.errors = self.errors.saturating_add(1);
+                self.last_error = Some((id, error));
+                self.error_log.append(&mut self.trussed, id, error);
+            }
+        }
+        result
+    }
+
+    fn exec<const N: usize>(&mut self, transport: Transport, command: Command, data: &[u8], response: &mut Vec<u8, N>) -> Result<(), Error> {
+        // Boot to mcuboot (not over NFC)
+        if command == Command::Update && transport == Transport::Contactless {
+            return Err(Error::NotAvailable);
+        }
+        // NFC policies are not set over the field they concern
+        let nfc_policy = matches!(command,
+            Command::SetFieldLossPolicy |
+            Command::SetNfcChainingPolicy |
+            Command::SetMaxNfcTransactions
+        );
+        if nfc_policy && transport == Transport::Contactless {
+            return Err(Error::NotAvailable);
+        }
+        // Chunked writes only ask for presence to commit their last chunk
+        let chunked = command == Command::SetEnterpriseRpList;
+        if self.requires_presence(command) && !chunked && !self.user_present() {
+            return Err(Error::NotAvailable);
+        }
+        if !command.is_idempotent() {
+            self.transactions.increment(&mut self.trussed);
+        }
 
+        match command {
+            Command::Reboot => {
+                self.error_log.flush(&mut self.trussed);
+                R::reboot()
+            }
+            Command::Locked => {
+                response.push(R::locked() as u8).ok();
+            }
+            Command::Rng => {
+                self.check_rng_health()?;
+                // Fill the HID packet (57 bytes)
+                if self.setting(Setting::RngMode) == 1 {
+                    let mut bytes = [0; 57];
+                    if !R::trng_bytes(&mut bytes) {
+                        return Err(Error::NotAvailable);
+                    }
+                    response.extend_from_slice(&bytes).ok();
+                } else {
+                    response.extend_from_slice(
+                        syscall!(self.trussed.random_bytes(57)).bytes.as_slice()
+                    ).ok();
+                }
+            }
+            Command::Update => {
+                // Refuse downgrades by more versions than the policy allows
+                if let Some(candidate) = R::update_candidate_version() {
+                    let allowed = self.setting(Setting::DowngradePolicy);
+                    if self.version.saturating_sub(candidate) > allowed {
+                        return Err(Error::NotAvailable);
+                    }
+                }
+                update::begin(&mut self.trussed, self.version);
+                self.error_log.flush(&mut self.trussed);
+                if data.first() == Some(&0x01) {
+                    R::reboot_to_firmware_update_destructive();
+                } else {
+                    R::reboot_to_firmware_update();
+                }
+            }
+            Command::Uuid => {
+                // Get UUID
+                response.extend_from_slice(&self.uuid).ok();
+            }
+            Command::Version => {
+                // GET VERSION
+                response.extend_from_slice(&self.version.to_be_bytes()).ok();
+            }
+            Command::Wink => {
+                // Flag 0x01 asks for diagnostics, on platforms with a display
+                if data.first() == Some(&0x01) && R::show_diagnostics() {
+                    return Ok(());
+                }
+                debug_now!("winking");
+                syscall!(self.trussed.wink(Duration::from_secs(10)));
+            }
+            Command::RngReseedInfo => {
+                let (interval, last_reseed) = R::rng_reseed_info().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&(interval.as_secs() as u32).to_be_bytes()).ok();
+                response.extend_from_slice(&(last_reseed.as_millis() as u64).to_be_bytes()).ok();
+            }
+            Command::RecoveryAttemptCount => {
+                let count = Counter::RecoveryAttempts.read(&mut self.trussed);
+                response.extend_from_slice(&u32::try_from(count).unwrap_or(u32::MAX).to_be_bytes()).ok();
+            }
+            Command::GetKeyAttestationPolicy => self.read_setting(Setting::KeyAttestationPolicy, response),
+            Command::SetKeyAttestationPolicy => self.write_setting(Setting::KeyAttestationPolicy, data)?,
+            Command::GetMaxClockSkew => self.read_setting(Setting::MaxClockSkew, response),
+            Command::SetMaxClockSkew => self.write_setting(Setting::MaxClockSkew, data)?,
+            Command::ProvisioningChecksum => self.provisioning_checksum(response),
+            Command::GetUnknownCommandPolicy => self.read_setting(Setting::UnknownCommandPolicy, response),
+            Command::SetUnknownCommandPolicy => self.write_setting(Setting::UnknownCommandPolicy, data)?,
+            Command::UpdateDownloadProgress => {
+                let progress = R::update_progress().ok_or(Error::NotAvailable)?;
+                response.push(progress.min(100)).ok();
+            }
+            Command::GetMaxFingerprints => self.read_setting(Setting::MaxFingerprints, response),
+            Command::SetMaxFingerprints => {
+                let capacity = R::fingerprint_capacity().ok_or(Error::NotAvailable)?;
+                self.write_bounded_setting(Setting::MaxFingerprints, data, capacity.into())?;
+            }
+            Command::EnrolledFingerprints => {
+                let count = R::enrolled_fingerprints().ok_or(Error::NotAvailable)?;
+                response.push(count).ok();
+            }
+            Command::GetBiometricFailPolicy => self.read_setting(Setting::BiometricFailPolicy, response),
+            Command::SetBiometricFailPolicy => self.write_setting(Setting::BiometricFailPolicy, data)?,
+            Command::TransactionCounter => {
+                let transactions = self.transactions.read(&mut self.trussed);
+                response.extend_from_slice(&transactions.to_be_bytes()).ok();
+            }
+            Command::SecureBootRollbackIndex => {
+                let index = R::secure_boot_rollback_index().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&index.to_be_bytes()).ok();
+            }
+            Command::PowerConsumption => {
+                let milliwatts = R::power_consumption_mw().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&milliwatts.to_be_bytes()).ok();
+            }
+            Command::GetPinLockoutDuration => self.read_setting(Setting::PinLockoutDuration, response),
+            Command::SetPinLockoutDuration => self.write_setting(Setting::PinLockoutDuration, data)?,
+            Command::HeapFragmentation => {
+                let fragmentation = R::heap_fragmentation().ok_or(Error::NotAvailable)?;
+                response.push(fragmentation.min(100)).ok();
+            }
+            Command::PresenceRequirements => {
+                let mut bitmap = [0u8; 32];
+                for id in 0..=u8::MAX {
+                    if Command::try_from(id).is_ok_and(|command| self.requires_presence(command)) {
+                        bitmap[id as usize / 8] |= 1 << (id % 8);
+                    }
+                }
+                response.extend_from_slice(&bitmap).ok();
+            }
+            Command::GetWriteConflictPolicy => self.read_setting(Setting::WriteConflictPolicy, response),
+            Command::SetWriteConflictPolicy => self.write_setting(Setting::WriteConflictPolicy, data)?,
+            Command::ConfigGeneration => {
+                let generation = self.config_generation();
+                response.extend_from_slice(&generation.to_be_bytes()).ok();
+            }
+            Command::GetRkEvictionPolicy => self.read_setting(Setting::RkEvictionPolicy, response),
+            Command::SetRkEvictionPolicy => self.write_setting(Setting::RkEvictionPolicy, data)?,
+            Command::AttestationSignCount => {
+                let count = R::attestation_sign_count().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&count.to_be_bytes()).ok();
+            }
+            Command::FirmwareVerifyTime => {
+                let milliseconds = R::last_verify_time_ms().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&milliseconds.to_be_bytes()).ok();
+            }
+            Command::GetDispatchPriority => self.read_setting(Setting::DispatchPriority, response),
+            Command::SetDispatchPriority => self.write_setting(Setting::DispatchPriority, data)?,
+            Command::GetTouchSensitivity => {
+                let sensitivity = R::touch_sensitivity().ok_or(Error::NotAvailable)?;
+                response.push(sensitivity).ok();
+            }
+            Command::SetTouchSensitivity => {
+                let sensitivity = match data {
+                    [sensitivity] => *sensitivity,
+                    _ => return Err(Error::InvalidLength),
+                };
+                if !R::set_touch_sensitivity(sensitivity) {
+                    return Err(Error::NotAvailable);
+                }
+            }
+            Command::ResponseBufferHighWater => {
+                response.extend_from_slice(&self.response_high_water.to_be_bytes()).ok();
+            }
+            Command::GetPresenceFailPolicy => self.read_setting(Setting::PresenceFailPolicy, response),
+            Command::SetPresenceFailPolicy => self.write_setting(Setting::PresenceFailPolicy, data)?,
+            Command::ErrorRate => {
+                let rate = (self.errors as u64 * 1000).checked_div(self.commands as u64).unwrap_or(0);
+                response.extend_from_slice(&(rate as u16).to_be_bytes()).ok();
+            }
+            Command::GetLargeBlobLimit => self.read_setting(Setting::LargeBlobLimit, response),
+            Command::SetLargeBlobLimit => {
+                let capacity = R::large_blob_capacity().ok_or(Error::NotAvailable)?;
+                self.write_bounded_setting(Setting::LargeBlobLimit, data, capacity)?;
+            }
+            Command::LargeBlobUsage => {
+                let usage = R::large_blob_usage().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&usage.to_be_bytes()).ok();
+            }
+            Command::GetCredBlobLimit => self.read_setting(Setting::CredBlobLimit, response),
+            Command::SetCredBlobLimit => self.write_setting(Setting::CredBlobLimit, data)?,
+            Command::TickResolution => {
+                response.extend_from_slice(&R::tick_resolution_us().to_be_bytes()).ok();
+            }
+            Command::GetUpdateCheckInterval => self.read_setting(Setting::UpdateCheckInterval, response),
+            Command::SetUpdateCheckInterval => self.write_setting(Setting::UpdateCheckInterval, data)?,
+            Command::RegisteredAppIds => {
+                for &id in R::app_ids() {
+                    response.extend_from_slice(&[id, R::app_enabled(id) as u8]).ok();
+                }
+            }
+            Command::GetResponseSigningPolicy => self.read_setting(Setting::ResponseSigningPolicy, response),
+            Command::SetResponseSigningPolicy => {
+                // Only require signatures the platform can make
+                // (the value is the last byte, after any generation)
+                if data.last() == Some(&1) && !R::can_sign_with_attestation_key() {
+                    return Err(Error::NotAvailable);
+                }
+                self.write_setting(Setting::ResponseSigningPolicy, data)?;
+            }
+            Command::CredentialIntegrity => {
+                let app_id = match data {
+                    [app_id] => *app_id,
+                    _ => return Err(Error::InvalidLength),
+                };
+                let corrupt = R::corrupt_credentials(app_id).ok_or(Error::NotAvailable)?;
+                response.push((corrupt == 0) as u8).ok();
+                response.extend_from_slice(&corrupt.to_be_bytes()).ok();
+            }
+            Command::GetPresenceQueueSize => self.read_setting(Setting::PresenceQueueSize, response),
+            Command::SetPresenceQueueSize => self.write_setting(Setting::PresenceQueueSize, data)?,
+            Command::RollbackFuseState => {
+                let fuses = R::rollback_fuse_state().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&fuses.to_be_bytes()).ok();
+            }
+            Command::GetFieldLossPolicy => self.read_setting(Setting::FieldLossPolicy, response),
+            Command::SetFieldLossPolicy => self.write_setting(Setting::FieldLossPolicy, data)?,
+            Command::CacheStats => {
+                response.extend_from_slice(&self.cache_hits.to_be_bytes()).ok();
+                response.extend_from_slice(&self.cache_misses.to_be_bytes()).ok();
+            }
+            Command::ProvisioningProgress => {
+                // Each step counts only once the previous ones are done
+                let key = R::attestation_key_provisioned();
+                let certificate = key && R::attestation_certificate().is_some();
+                let step = if certificate && R::locked() {
+                    3
+                } else if certificate {
+                    2
+                } else if key {
+                    1
+                } else {
+                    0
+                };
+                response.push(step).ok();
+            }
+            Command::GetEnterpriseRpList => {
+                let list = self.enterprise_rp_list();
+                response.extend_from_slice(&list).ok();
+            }
+            Command::SetEnterpriseRpList => self.write_enterprise_rp_list(transport, data)?,
+            Command::ThrottleEventCount => {
+                let count = Counter::ThrottleEvents.read(&mut self.trussed);
+                response.extend_from_slice(&u32::try_from(count).unwrap_or(u32::MAX).to_be_bytes()).ok();
+            }
+            Command::GetRngFailAction => self.read_setting(Setting::RngFailAction, response),
+            Command::SetRngFailAction => self.write_setting(Setting::RngFailAction, data)?,
+            Command::DebugPortStatus => {
+                response.push(R::debug_port_locked() as u8).ok();
+            }
+            Command::MaxFirmwareImageSize => {
+                let size = R::max_image_size().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&size.to_be_bytes()).ok();
+            }
+            Command::CryptoThroughput => {
+                let ops = R::crypto_ops_per_sec().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&ops.to_be_bytes()).ok();
+            }
+            Command::GetPinComplexity => self.read_setting(Setting::PinComplexity, response),
+            Command::SetPinComplexity => self.write_setting(Setting::PinComplexity, data)?,
+            Command::UptimeSinceLastCrash => {
+                let uptime = R::uptime_since_crash_ms()
+                    .unwrap_or_else(|| syscall!(self.trussed.uptime()).uptime.as_millis() as u64);
+                response.extend_from_slice(&uptime.to_be_bytes()).ok();
+            }
+            Command::SeCommErrorCount => {
+                let errors = R::se_comm_errors().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&errors.to_be_bytes()).ok();
+            }
+            Command::GetConveyancePolicy => self.read_setting(Setting::ConveyancePolicy, response),
+            Command::SetConveyancePolicy => self.write_setting(Setting::ConveyancePolicy, data)?,
+            Command::WearLevelingStatus => {
+                let health = R::wear_leveling_status().ok_or(Error::NotAvailable)?;
+                response.push(health).ok();
+            }
+            Command::GetMaxConcurrentAssertions => self.read_setting(Setting::MaxConcurrentAssertions, response),
+            Command::SetMaxConcurrentAssertions => self.write_setting(Setting::MaxConcurrentAssertions, data)?,
+            Command::InterruptLatency => {
+                let microseconds = R::max_interrupt_latency_us().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&microseconds.to_be_bytes()).ok();
+            }
+            Command::GetCredentialNamingPolicy => self.read_setting(Setting::CredentialNamingPolicy, response),
+            Command::SetCredentialNamingPolicy => self.write_setting(Setting::CredentialNamingPolicy, data)?,
+            Command::FreeCounterSlots => {
+                let slots = R::free_counter_slots().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&slots.to_be_bytes()).ok();
+            }
+            Command::GetPanicPolicy => self.read_setting(Setting::PanicPolicy, response),
+            Command::SetPanicPolicy => self.write_setting(Setting::PanicPolicy, data)?,
+            Command::ConcurrencyState => {
+                let (holder, waiting) = R::dispatch_lock_state().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&[holder, waiting as u8]).ok();
+            }
+            Command::GetUserHandleLimit => self.read_setting(Setting::UserHandleLimit, response),
+            Command::SetUserHandleLimit => self.write_setting(Setting::UserHandleLimit, data)?,
+            Command::BootloaderProtocolVersion => {
+                let version = R::bootloader_protocol_version().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&version.to_be_bytes()).ok();
+            }
+            Command::GetMaxCertificates => self.read_setting(Setting::MaxCertificates, response),
+            Command::SetMaxCertificates => {
+                let capacity = R::certificate_capacity().ok_or(Error::NotAvailable)?;
+                self.write_bounded_setting(Setting::MaxCertificates, data, capacity.into())?;
+            }
+            Command::TotalSignatures => {
+                // Apps persist their signature counters exactly anyway,
+                // unlike a batched counter that skips ahead on reboot
+                let signatures = R::app_ids()
+                    .iter()
+                    .filter_map(|&id| R::signature_count(id))
+                    .fold(None, |total: Option<u64>, count| Some(total.unwrap_or(0).saturating_add(count)))
+                    .ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&signatures.to_be_bytes()).ok();
+            }
+            Command::GetNfcChainingPolicy => self.read_setting(Setting::NfcChainingPolicy, response),
+            Command::SetNfcChainingPolicy => self.write_setting(Setting::NfcChainingPolicy, data)?,
+            Command::SelfTestSchedule => {
+                self.read_setting(Setting::SelfTestInterval, response);
+                let last_run = R::last_self_test().map_or(u64::MAX, |uptime| uptime.as_millis() as u64);
+                response.extend_from_slice(&last_run.to_be_bytes()).ok();
+            }
+            Command::SetSelfTestInterval => self.write_setting(Setting::SelfTestInterval, data)?,
+            Command::GetDowngradePolicy => self.read_setting(Setting::DowngradePolicy, response),
+            Command::SetDowngradePolicy => {
+                // Confirmed by a second presence prompt, as it weakens rollback protection
+                if !self.user_present() {
+                    return Err(Error::NotAvailable);
+                }
+                self.write_setting(Setting::DowngradePolicy, data)?
+            }
+            Command::EntropyPoolLevel => {
+                let level = R::entropy_pool_percent().ok_or(Error::NotAvailable)?;
+                response.push(level.min(100)).ok();
+            }
+            Command::GetCredentialTtl => self.read_setting(Setting::CredentialTtl, response),
+            Command::SetCredentialTtl => self.write_setting(Setting::CredentialTtl, data)?,
+            Command::DispatchStackUsage => {
+                let bytes = R::dispatch_stack_usage().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&bytes.to_be_bytes()).ok();
+            }
+            Command::GetMaxNfcTransactions => self.read_setting(Setting::MaxNfcTransactions, response),
+            Command::SetMaxNfcTransactions => self.write_setting(Setting::MaxNfcTransactions, data)?,
+            Command::LastError => {
+                let (id, error) = self.last_error.map_or((0, 0), |(id, error)| (id, error.into()));
+                response.extend_from_slice(&[id, error]).ok();
+            }
+            Command::GetKeyProtectionLevel => self.read_setting(Setting::KeyProtectionLevel, response),
+            Command::SetKeyProtectionLevel => self.write_setting(Setting::KeyProtectionLevel, data)?,
+            Command::PeakCommandMemory => {
+                let bytes = R::peak_command_alloc().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&bytes.to_be_bytes()).ok();
+            }
+            Command::GetExpiredCertPolicy => self.read_setting(Setting::ExpiredCertPolicy, response),
+            Command::SetExpiredCertPolicy => self.write_setting(Setting::ExpiredCertPolicy, data)?,
+            Command::UsbDescriptorConfig => {
+                let (vendor_id, product_id, interfaces) = R::usb_descriptor_config().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&vendor_id.to_be_bytes()).ok();
+                response.extend_from_slice(&product_id.to_be_bytes()).ok();
+                response.push(interfaces).ok();
+            }
+            Command::GetChunkSize => self.read_setting(Setting::ChunkSize, response),
+            Command::SetChunkSize => {
+                // Bounded by the longest response of the transport it is set over
+                let limit = self.response_limit::<N>(transport);
+                self.write_bounded_setting(Setting::ChunkSize, data, limit as u32)?;
+            }
+            Command::PersistentErrorLog => {
+                let start = match data {
+                    [] => 0,
+                    [start] => *start as usize,
+                    _ => return Err(Error::InvalidLength),
+                };
+                let log = self.error_log.read(&mut self.trussed);
+                let chunk_size = (self.setting(Setting::ChunkSize) as usize).min(N);
+                let chunk = log.chunks_exact(2).skip(start).take((chunk_size / 2).max(1));
+                for entry in chunk {
+                    response.extend_from_slice(entry).ok();
+                }
+            }
+            Command::ClearPersistentErrorLog => {
+                self.error_log.clear(&mut self.trussed);
+            }
+            Command::GetBackupEligibility => self.read_setting(Setting::BackupEligibility, response),
+            Command::SetBackupEligibility => self.write_setting(Setting::BackupEligibility, data)?,
+            Command::SchedulerDrift => {
+                let drift = R::scheduler_drift_ppm().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&drift.to_be_bytes()).ok();
+            }
+            Command::FirmwareChecksum => {
+                let crc = R::firmware_crc32().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&crc.to_be_bytes()).ok();
+            }
+            Command::GetSignatureUpPolicy => self.read_setting(Setting::SignatureUpPolicy, response),
+            Command::SetSignatureUpPolicy => self.write_setting(Setting::SignatureUpPolicy, data)?,
+            Command::ExpiringCredentials => {
+                let window = <[u8; 4]>::try_from(data).map_err(|_| Error::InvalidLength)?;
+                if self.setting(Setting::CredentialTtl) == 0 {
+                    return Err(Error::NotAvailable);
+                }
+                let window = Duration::from_secs(u32::from_be_bytes(window).into());
+                let count = R::credentials_expiring_within(window).ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&count.to_be_bytes()).ok();
+            }
+            Command::GetRngMode => self.read_setting(Setting::RngMode, response),
+            Command::SetRngMode => {
+                if data.last() == Some(&1) && !R::has_trng() {
+                    return Err(Error::NotAvailable);
+                }
+                self.write_setting(Setting::RngMode, data)?
+            }
+            Command::DispatcherVersion => {
+                let version = R::dispatcher_version().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&version.to_be_bytes()).ok();
+            }
+            Command::RailVoltages => {
+                let rail = match data {
+                    [rail] => *rail,
+                    _ => return Err(Error::InvalidLength),
+                };
+                if R::rail_count() == 0 {
+                    return Err(Error::NotAvailable);
+                }
+                if rail >= R::rail_count() {
+                    return Err(Error::UnsupportedCommand);
+                }
+                let millivolts = R::rail_voltage_mv(rail).ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&millivolts.to_be_bytes()).ok();
+            }
+            Command::GetOriginBindingPolicy => self.read_setting(Setting::OriginBindingPolicy, response),
+            Command::SetOriginBindingPolicy => self.write_setting(Setting::OriginBindingPolicy, data)?,
+            Command::LastKeepaliveCount => {
+                let count = R::last_keepalive_count().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&count.to_be_bytes()).ok();
+            }
+            Command::GetSignCountPolicy => self.read_setting(Setting::SignCountPolicy, response),
+            Command::SetSignCountPolicy => self.write_setting(Setting::SignCountPolicy, data)?,
+            Command::TotalBytesWritten => {
+                let bytes = R::total_flash_bytes_written().ok_or(Error::NotAvailable)?;
+                response.extend_from_slice(&bytes.to_be_bytes()).ok();
+            }
+            Command::GetUnknownInsPolicy => self.read_setting(Setting::UnknownInsPolicy, response),
+            Command::SetUnknownInsPolicy => self.write_setting(Setting::UnknownInsPolicy, data)?,
+            Command::Liveness => {
+                let uptime = syscall!(self.trussed.uptime()).uptime;
+                response.extend_from_slice(&(uptime.as_secs() as u32).to_be_bytes()).ok();
+            }
+            Command::GetDiagnosticsPolicy => {
+                response.push(self.diagnostics_require_presence as u8).ok();
+            }
+            Command::SuccessfulUpdateCount => {
+                let count = Counter::SuccessfulUpdates.read(&mut self.trussed);
+                response.extend_from_slice(&u32::try_from(count).unwrap_or(u32::MAX).to_be_bytes()).ok();
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<T, R> hid::App for App<T, R>
@@ -79,51 +1210,23 @@ where T: TrussedClient,
             HidCommand::Vendor(VERSION),
             HidCommand::Vendor(UUID),
             HidCommand::Vendor(LOCKED),
+            HidCommand::Vendor(EXTENDED),
         ]
     }
 
     fn call(&mut self, command: HidCommand, input_data: &Message, response: &mut Message) -> hid::AppResult {
-        match command {
-            HidCommand::Vendor(REBOOT) => R::reboot(),
-            HidCommand::Vendor(LOCKED) => {
-                response.extend_from_slice(
-                    &[R::locked() as u8]
-                ).ok();
-            }
-            HidCommand::Vendor(RNG) => {
-                // Fill the HID packet (57 bytes)
-                response.extend_from_slice(
-                    &syscall!(self.trussed.random_bytes(57)).bytes.as_slice()
-                ).ok();
-            }
-            HidCommand::Vendor(UPDATE) => {
-                if self.user_present() {
-                    if input_data.len() > 0 && input_data[0] == 0x01 {
-                        R::reboot_to_firmware_update_destructive();
-                    } else {
-                        R::reboot_to_firmware_update();
-                    }
-                } else {
-                    return Err(hid::Error::InvalidLength);
-                }
-            }
-            HidCommand::Vendor(UUID) => {
-                // Get UUID
-                response.extend_from_slice(&self.uuid).ok();
-            }
-            HidCommand::Vendor(VERSION) => {
-                // GET VERSION
-                response.extend_from_slice(&self.version.to_be_bytes()).ok();
-            }
-            HidCommand::Wink => {
-                debug_now!("winking");
-                syscall!(self.trussed.wink(core::time::Duration::from_secs(10)));
+        let (id, data) = match command {
+            HidCommand::Wink => (Command::Wink.into(), &input_data[..]),
+            HidCommand::Vendor(EXTENDED) => {
+                let (&id, data) = input_data.split_first().ok_or(hid::Error::InvalidLength)?;
+                (id, data)
             }
+            HidCommand::Vendor(command) => (command as u8, &input_data[..]),
             _ => {
                 return Err(hid::Error::InvalidCommand);
             }
-        }
-        Ok(())
+        };
+        Ok(self.dispatch(Transport::Hid, id, data, response)?)
     }
 }
 
@@ -142,58 +1245,40 @@ where T: TrussedClient,
       R: Reboot
 {
 
-    fn select(&mut self, _apdu: &Command, _reply: &mut response::Data) -> apdu::Result {
+    fn select(&mut self, _apdu: &ApduCommand, _reply: &mut response::Data) -> apdu::Result {
         Ok(())
     }
 
     fn deselect(&mut self) {}
 
-    fn call(&mut self, interface: apdu::Interface, apdu: &Command, reply: &mut response::Data) -> apdu::Result {
+    fn call(&mut self, interface: apdu::Interface, apdu: &ApduCommand, reply: &mut response::Data) -> apdu::Result {
         let instruction: u8 = apdu.instruction().into();
 
-        if instruction == 0x08 {
-            syscall!(self.trussed.wink(core::time::Duration::from_secs(10)));
-            return Ok(());
-        }
-
-        let command: VendorCommand = instruction.try_into().map_err(|_e| Status::InstructionNotSupportedOrInvalid)?;
-
-        match command {
-            REBOOT => R::reboot(),
-            LOCKED => {
-                // Random bytes
-                reply.extend_from_slice(&[R::locked() as u8]).ok();
-            }
-            RNG => {
-                // Random bytes
-                reply.extend_from_slice(&syscall!(self.trussed.random_bytes(57)).bytes.as_slice()).ok();
-            }
-            UPDATE => {
-                // Boot to mcuboot (only when contact interface)
-                if interface == apdu::Interface::Contact && self.user_present()
-                {
-                    if apdu.p1 == 0x01 {
-                        R::reboot_to_firmware_update_destructive();
-                    } else {
-                        R::reboot_to_firmware_update();
-                    }
-                }
-                return Err(Status::ConditionsOfUseNotSatisfied);
-            }
-            UUID => {
-                // Get UUID
-                reply.extend_from_slice(&self.uuid).ok();
-            }
-            VERSION => {
-                // Get version
-                reply.extend_from_slice(&self.version.to_be_bytes()[..]).ok();
-            }
-
-            _ => return Err(Status::InstructionNotSupportedOrInvalid),
+        // The vendor commands carry their flag in P1 rather than in the data,
+        // all other commands are only reachable through EXTENDED.
+        let flag = [apdu.p1];
+        let vendor = [UPDATE, REBOOT, RNG, VERSION, UUID, LOCKED]
+            .iter()
+            .any(|&command| command as u8 == instruction);
+        let (id, data) = if instruction == EXTENDED as u8 {
+            let (&id, data) = apdu.data().split_first().ok_or(Status::WrongLength)?;
+            (id, data)
+        } else if vendor || instruction == u8::from(Command::Wink) {
+            (instruction, &flag[..])
+        } else {
+            return Err(self.unknown_instruction_status());
+        };
 
+        let transport = match interface {
+            apdu::Interface::Contact => Transport::Contact,
+            apdu::Interface::Contactless => Transport::Contactless,
+        };
+        let unknown = Command::try_from(id).is_err();
+        match self.dispatch(transport, id, data, reply) {
+            Err(_) if unknown => return Err(self.unknown_instruction_status()),
+            result => result?,
         }
+        self.response_high_water = self.response_high_water.max(reply.len() as u32);
         Ok(())
-
     }
 }
-
@@ -0,0 +1,826 @@
+//! Commands understood by the admin app, independent of the transport.
+//!
+//! The original commands each have a CTAPHID vendor command of their own,
+//! which doubles as their APDU instruction byte. All other commands are
+//! multiplexed through [`EXTENDED`]: the first byte of the request data is
+//! the command id, the remaining bytes are the command's payload. This
+//! framing is the same over CTAPHID and APDU.
+//!
+//! Command ids are grouped as follows:
+//! - `0x01..=0x3F`: commands without a vendor command of their own,
+//! - `0x40..=0x7F`: the vendor commands (plus `0x08`, wink),
+//! - `0x80..=0xBF`: reading a setting,
+//! - `0xC0..=0xFF`: writing a setting (same low bits as reading it).
+//!
+//! The id `0x00` is never assigned, so it can be used to signal "no command".
+//! The id `0x01` is reserved for reporting pending long operations.
+//! The ids `0x94` and `0xD4` are reserved for a minimum host entropy setting.
+
+use core::convert::TryFrom;
+use ctaphid_dispatch::app as hid;
+use ctaphid_dispatch::command::VendorCommand;
+use apdu_dispatch::iso7816::Status;
+
+pub(crate) const UPDATE: VendorCommand = VendorCommand::H51;
+pub(crate) const REBOOT: VendorCommand = VendorCommand::H53;
+pub(crate) const RNG: VendorCommand = VendorCommand::H60;
+pub(crate) const VERSION: VendorCommand = VendorCommand::H61;
+pub(crate) const UUID: VendorCommand = VendorCommand::H62;
+pub(crate) const LOCKED: VendorCommand = VendorCommand::H63;
+pub(crate) const EXTENDED: VendorCommand = VendorCommand::H64;
+
+const WINK: u8 = 0x08;
+const RNG_RESEED_INFO: u8 = 0x02;
+const RECOVERY_ATTEMPT_COUNT: u8 = 0x03;
+const PROVISIONING_CHECKSUM: u8 = 0x04;
+const UPDATE_DOWNLOAD_PROGRESS: u8 = 0x05;
+const ENROLLED_FINGERPRINTS: u8 = 0x06;
+const TRANSACTION_COUNTER: u8 = 0x07;
+const SECURE_BOOT_ROLLBACK_INDEX: u8 = 0x09;
+const POWER_CONSUMPTION: u8 = 0x0A;
+const HEAP_FRAGMENTATION: u8 = 0x0B;
+const PRESENCE_REQUIREMENTS: u8 = 0x0C;
+const CONFIG_GENERATION: u8 = 0x0D;
+const ATTESTATION_SIGN_COUNT: u8 = 0x0E;
+const FIRMWARE_VERIFY_TIME: u8 = 0x0F;
+const RESPONSE_BUFFER_HIGH_WATER: u8 = 0x10;
+const ERROR_RATE: u8 = 0x11;
+const LARGE_BLOB_USAGE: u8 = 0x12;
+const TICK_RESOLUTION: u8 = 0x13;
+const REGISTERED_APP_IDS: u8 = 0x14;
+const CREDENTIAL_INTEGRITY: u8 = 0x15;
+const ROLLBACK_FUSE_STATE: u8 = 0x16;
+const CACHE_STATS: u8 = 0x17;
+const PROVISIONING_PROGRESS: u8 = 0x18;
+const THROTTLE_EVENT_COUNT: u8 = 0x19;
+const DEBUG_PORT_STATUS: u8 = 0x1A;
+const MAX_FIRMWARE_IMAGE_SIZE: u8 = 0x1B;
+const CRYPTO_THROUGHPUT: u8 = 0x1C;
+const UPTIME_SINCE_LAST_CRASH: u8 = 0x1D;
+const SE_COMM_ERROR_COUNT: u8 = 0x1E;
+const WEAR_LEVELING_STATUS: u8 = 0x1F;
+const INTERRUPT_LATENCY: u8 = 0x20;
+const FREE_COUNTER_SLOTS: u8 = 0x21;
+const CONCURRENCY_STATE: u8 = 0x22;
+const BOOTLOADER_PROTOCOL_VERSION: u8 = 0x23;
+const TOTAL_SIGNATURES: u8 = 0x24;
+const ENTROPY_POOL_LEVEL: u8 = 0x25;
+const DISPATCH_STACK_USAGE: u8 = 0x26;
+const LAST_ERROR: u8 = 0x27;
+const PEAK_COMMAND_MEMORY: u8 = 0x28;
+const USB_DESCRIPTOR_CONFIG: u8 = 0x29;
+const PERSISTENT_ERROR_LOG: u8 = 0x2A;
+const CLEAR_PERSISTENT_ERROR_LOG: u8 = 0x2B;
+const SCHEDULER_DRIFT: u8 = 0x2C;
+const FIRMWARE_CHECKSUM: u8 = 0x2D;
+const EXPIRING_CREDENTIALS: u8 = 0x2E;
+const DISPATCHER_VERSION: u8 = 0x2F;
+const RAIL_VOLTAGES: u8 = 0x30;
+const LAST_KEEPALIVE_COUNT: u8 = 0x31;
+const TOTAL_BYTES_WRITTEN: u8 = 0x32;
+const LIVENESS: u8 = 0x33;
+const GET_DIAGNOSTICS_POLICY: u8 = 0x34;
+const SUCCESSFUL_UPDATE_COUNT: u8 = 0x35;
+const GET_KEY_ATTESTATION_POLICY: u8 = 0x80;
+const GET_MAX_CLOCK_SKEW: u8 = 0x81;
+const GET_UNKNOWN_COMMAND_POLICY: u8 = 0x82;
+const GET_MAX_FINGERPRINTS: u8 = 0x83;
+const GET_BIOMETRIC_FAIL_POLICY: u8 = 0x84;
+const GET_PIN_LOCKOUT_DURATION: u8 = 0x85;
+const GET_WRITE_CONFLICT_POLICY: u8 = 0x86;
+const GET_RK_EVICTION_POLICY: u8 = 0x87;
+const GET_DISPATCH_PRIORITY: u8 = 0x88;
+const GET_TOUCH_SENSITIVITY: u8 = 0x89;
+const GET_PRESENCE_FAIL_POLICY: u8 = 0x8A;
+const GET_LARGE_BLOB_LIMIT: u8 = 0x8B;
+const GET_CRED_BLOB_LIMIT: u8 = 0x8C;
+const GET_UPDATE_CHECK_INTERVAL: u8 = 0x8D;
+const GET_RESPONSE_SIGNING_POLICY: u8 = 0x8E;
+const GET_PRESENCE_QUEUE_SIZE: u8 = 0x8F;
+const GET_FIELD_LOSS_POLICY: u8 = 0x90;
+const GET_ENTERPRISE_RP_LIST: u8 = 0x91;
+const GET_RNG_FAIL_ACTION: u8 = 0x92;
+const GET_PIN_COMPLEXITY: u8 = 0x93;
+const GET_CONVEYANCE_POLICY: u8 = 0x95;
+const GET_MAX_CONCURRENT_ASSERTIONS: u8 = 0x96;
+const GET_CREDENTIAL_NAMING_POLICY: u8 = 0x97;
+const GET_PANIC_POLICY: u8 = 0x98;
+const GET_USER_HANDLE_LIMIT: u8 = 0x99;
+const GET_MAX_CERTIFICATES: u8 = 0x9A;
+const GET_NFC_CHAINING_POLICY: u8 = 0x9B;
+const SELF_TEST_SCHEDULE: u8 = 0x9C;
+const GET_DOWNGRADE_POLICY: u8 = 0x9D;
+const GET_CREDENTIAL_TTL: u8 = 0x9E;
+const GET_MAX_NFC_TRANSACTIONS: u8 = 0x9F;
+const GET_KEY_PROTECTION_LEVEL: u8 = 0xA0;
+const GET_EXPIRED_CERT_POLICY: u8 = 0xA1;
+const GET_CHUNK_SIZE: u8 = 0xA2;
+const GET_BACKUP_ELIGIBILITY: u8 = 0xA3;
+const GET_SIGNATURE_UP_POLICY: u8 = 0xA4;
+const GET_RNG_MODE: u8 = 0xA5;
+const GET_ORIGIN_BINDING_POLICY: u8 = 0xA6;
+const GET_SIGN_COUNT_POLICY: u8 = 0xA7;
+const GET_UNKNOWN_INS_POLICY: u8 = 0xA8;
+const SET_KEY_ATTESTATION_POLICY: u8 = 0xC0;
+const SET_MAX_CLOCK_SKEW: u8 = 0xC1;
+const SET_UNKNOWN_COMMAND_POLICY: u8 = 0xC2;
+const SET_MAX_FINGERPRINTS: u8 = 0xC3;
+const SET_BIOMETRIC_FAIL_POLICY: u8 = 0xC4;
+const SET_PIN_LOCKOUT_DURATION: u8 = 0xC5;
+const SET_WRITE_CONFLICT_POLICY: u8 = 0xC6;
+const SET_RK_EVICTION_POLICY: u8 = 0xC7;
+const SET_DISPATCH_PRIORITY: u8 = 0xC8;
+const SET_TOUCH_SENSITIVITY: u8 = 0xC9;
+const SET_PRESENCE_FAIL_POLICY: u8 = 0xCA;
+const SET_LARGE_BLOB_LIMIT: u8 = 0xCB;
+const SET_CRED_BLOB_LIMIT: u8 = 0xCC;
+const SET_UPDATE_CHECK_INTERVAL: u8 = 0xCD;
+const SET_RESPONSE_SIGNING_POLICY: u8 = 0xCE;
+const SET_PRESENCE_QUEUE_SIZE: u8 = 0xCF;
+const SET_FIELD_LOSS_POLICY: u8 = 0xD0;
+const SET_ENTERPRISE_RP_LIST: u8 = 0xD1;
+const SET_RNG_FAIL_ACTION: u8 = 0xD2;
+const SET_PIN_COMPLEXITY: u8 = 0xD3;
+const SET_CONVEYANCE_POLICY: u8 = 0xD5;
+const SET_MAX_CONCURRENT_ASSERTIONS: u8 = 0xD6;
+const SET_CREDENTIAL_NAMING_POLICY: u8 = 0xD7;
+const SET_PANIC_POLICY: u8 = 0xD8;
+const SET_USER_HANDLE_LIMIT: u8 = 0xD9;
+const SET_MAX_CERTIFICATES: u8 = 0xDA;
+const SET_NFC_CHAINING_POLICY: u8 = 0xDB;
+const SET_SELF_TEST_INTERVAL: u8 = 0xDC;
+const SET_DOWNGRADE_POLICY: u8 = 0xDD;
+const SET_CREDENTIAL_TTL: u8 = 0xDE;
+const SET_MAX_NFC_TRANSACTIONS: u8 = 0xDF;
+const SET_KEY_PROTECTION_LEVEL: u8 = 0xE0;
+const SET_EXPIRED_CERT_POLICY: u8 = 0xE1;
+const SET_CHUNK_SIZE: u8 = 0xE2;
+const SET_BACKUP_ELIGIBILITY: u8 = 0xE3;
+const SET_SIGNATURE_UP_POLICY: u8 = 0xE4;
+const SET_RNG_MODE: u8 = 0xE5;
+const SET_ORIGIN_BINDING_POLICY: u8 = 0xE6;
+const SET_SIGN_COUNT_POLICY: u8 = 0xE7;
+const SET_UNKNOWN_INS_POLICY: u8 = 0xE8;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+    Update,
+    Reboot,
+    Rng,
+    Version,
+    Uuid,
+    Locked,
+    Wink,
+    /// RNG reseed interval in seconds, and uptime of the last reseed in milliseconds.
+    RngReseedInfo,
+    /// Times the device entered its recovery mode.
+    RecoveryAttemptCount,
+    GetKeyAttestationPolicy,
+    SetKeyAttestationPolicy,
+    GetMaxClockSkew,
+    SetMaxClockSkew,
+    /// SHA-256 over the provisioned state, to confirm a known-good provisioning.
+    ProvisioningChecksum,
+    GetUnknownCommandPolicy,
+    SetUnknownCommandPolicy,
+    /// Progress of a firmware update download, in percent.
+    UpdateDownloadProgress,
+    GetMaxFingerprints,
+    SetMaxFingerprints,
+    /// Number of enrolled fingerprint templates.
+    EnrolledFingerprints,
+    GetBiometricFailPolicy,
+    SetBiometricFailPolicy,
+    /// Non-idempotent commands executed over the device's lifetime.
+    TransactionCounter,
+    /// Rollback index of the secure-boot subsystem.
+    SecureBootRollbackIndex,
+    /// Estimated power consumption, in milliwatts.
+    PowerConsumption,
+    GetPinLockoutDuration,
+    SetPinLockoutDuration,
+    /// Heap fragmentation, in percent.
+    HeapFragmentation,
+    /// Bitmap of the command ids requiring user presence,
+    /// bit `id % 8` of byte `id / 8`.
+    PresenceRequirements,
+    GetWriteConflictPolicy,
+    SetWriteConflictPolicy,
+    /// Number of setting writes, to detect conflicting writes.
+    ConfigGeneration,
+    GetRkEvictionPolicy,
+    SetRkEvictionPolicy,
+    /// Global attestation signature counter.
+    AttestationSignCount,
+    /// Duration of the last firmware signature verification, in milliseconds.
+    FirmwareVerifyTime,
+    GetDispatchPriority,
+    SetDispatchPriority,
+    GetTouchSensitivity,
+    SetTouchSensitivity,
+    /// Largest APDU response since boot, in bytes, which the APDU
+    /// dispatch holds in its response chaining buffer.
+    ResponseBufferHighWater,
+    GetPresenceFailPolicy,
+    SetPresenceFailPolicy,
+    /// Failed commands per 1000 commands since boot.
+    ErrorRate,
+    GetLargeBlobLimit,
+    SetLargeBlobLimit,
+    /// Bytes of largeBlob storage in use.
+    LargeBlobUsage,
+    GetCredBlobLimit,
+    SetCredBlobLimit,
+    /// Microseconds per tick of the system timer.
+    TickResolution,
+    GetUpdateCheckInterval,
+    SetUpdateCheckInterval,
+    /// Id and enabled flag of each app registered with the dispatchers.
+    RegisteredAppIds,
+    GetResponseSigningPolicy,
+    SetResponseSigningPolicy,
+    /// Whether the credentials stored by an app are intact, and how many are corrupt.
+    CredentialIntegrity,
+    GetPresenceQueueSize,
+    SetPresenceQueueSize,
+    /// Bitmap of the burned rollback protection fuses.
+    RollbackFuseState,
+    GetFieldLossPolicy,
+    SetFieldLossPolicy,
+    /// Hits and misses of the setting cache on setting reads by the host.
+    CacheStats,
+    /// Provisioning step reached: `0` blank, `1` attestation key
+    /// generated, `2` certificate imported, `3` locked.
+    ProvisioningProgress,
+    GetEnterpriseRpList,
+    SetEnterpriseRpList,
+    /// Times the device was throttled for its temperature.
+    ThrottleEventCount,
+    GetRngFailAction,
+    SetRngFailAction,
+    /// Whether the hardware debug port is locked.
+    DebugPortStatus,
+    /// Largest firmware image the device accepts, in bytes.
+    MaxFirmwareImageSize,
+    /// Estimated cryptographic operations per second.
+    CryptoThroughput,
+    GetPinComplexity,
+    SetPinComplexity,
+    /// Milliseconds since the last crash, or since boot if there was none.
+    UptimeSinceLastCrash,
+    /// Communication errors with the secure element.
+    SeCommErrorCount,
+    GetConveyancePolicy,
+    SetConveyancePolicy,
+    /// Health of the flash wear leveling, as reported by the platform.
+    WearLevelingStatus,
+    GetMaxConcurrentAssertions,
+    SetMaxConcurrentAssertions,
+    /// Longest measured interrupt latency, in microseconds.
+    InterruptLatency,
+    GetCredentialNamingPolicy,
+    SetCredentialNamingPolicy,
+    /// Persistent monotonic counter slots still available.
+    FreeCounterSlots,
+    GetPanicPolicy,
+    SetPanicPolicy,
+    /// Interface holding the dispatch lock, and whether another one waits for it.
+    ConcurrencyState,
+    GetUserHandleLimit,
+    SetUserHandleLimit,
+    /// Version of the bootloader's update protocol.
+    BootloaderProtocolVersion,
+    GetMaxCertificates,
+    SetMaxCertificates,
+    /// Signatures made by all apps, summed over the apps counting them.
+    TotalSignatures,
+    GetNfcChainingPolicy,
+    SetNfcChainingPolicy,
+    /// Self-test interval in seconds, and uptime of the last self-test run
+    /// in milliseconds, all ones if there was none since boot.
+    SelfTestSchedule,
+    SetSelfTestInterval,
+    GetDowngradePolicy,
+    SetDowngradePolicy,
+    /// Fill level of the entropy pool, in percent.
+    EntropyPoolLevel,
+    GetCredentialTtl,
+    SetCredentialTtl,
+    /// Peak stack usage of the dispatch task, in bytes.
+    DispatchStackUsage,
+    GetMaxNfcTransactions,
+    SetMaxNfcTransactions,
+    /// Id of the last failed command and its error code,
+    /// cleared by the next successful command.
+    LastError,
+    GetKeyProtectionLevel,
+    SetKeyProtectionLevel,
+    /// Peak memory allocated while processing the last command, in bytes.
+    PeakCommandMemory,
+    GetExpiredCertPolicy,
+    SetExpiredCertPolicy,
+    /// USB vendor and product id, and bitmap of the USB interfaces.
+    UsbDescriptorConfig,
+    GetChunkSize,
+    SetChunkSize,
+    /// Failed commands logged across reboots, from the entry
+    /// at the given index on, as many as fit the chunk size but at least one.
+    ///
+    /// Entries are written in batches of eight, so unplugging the device
+    /// loses up to seven of the latest entries.
+    PersistentErrorLog,
+    /// Clears the persistent log of failed commands.
+    ClearPersistentErrorLog,
+    GetBackupEligibility,
+    SetBackupEligibility,
+    /// Drift of the scheduler tick, in parts per million.
+    SchedulerDrift,
+    /// CRC32 of the firmware image.
+    FirmwareChecksum,
+    GetSignatureUpPolicy,
+    SetSignatureUpPolicy,
+    /// Resident credentials expiring within the given number of seconds.
+    ExpiringCredentials,
+    GetRngMode,
+    SetRngMode,
+    /// Version of the runner's dispatchers.
+    DispatcherVersion,
+    /// Voltage of the given power rail, in millivolts.
+    RailVoltages,
+    GetOriginBindingPolicy,
+    SetOriginBindingPolicy,
+    /// Keepalives sent during the last presence prompt or long operation.
+    LastKeepaliveCount,
+    GetSignCountPolicy,
+    SetSignCountPolicy,
+    /// Bytes written to persistent storage over the device's lifetime.
+    TotalBytesWritten,
+    GetUnknownInsPolicy,
+    SetUnknownInsPolicy,
+    /// Token advancing every second while the firmware's main loop runs.
+    Liveness,
+    /// Whether commands reading diagnostics require user presence.
+    GetDiagnosticsPolicy,
+    /// Firmware updates that completed.
+    SuccessfulUpdateCount,
+}
+
+impl Command {
+    /// Whether the command asks for user presence before executing
+    /// (for chunked writes, before committing the last chunk).
+    pub fn requires_presence(self) -> bool {
+        matches!(self,
+            Command::Update |
+            Command::SetKeyAttestationPolicy |
+            Command::SetMaxClockSkew |
+            Command::SetUnknownCommandPolicy |
+            Command::SetMaxFingerprints |
+            Command::SetBiometricFailPolicy |
+            Command::SetPinLockoutDuration |
+            Command::SetWriteConflictPolicy |
+            Command::SetRkEvictionPolicy |
+            Command::SetDispatchPriority |
+            Command::SetPresenceFailPolicy |
+            Command::SetLargeBlobLimit |
+            Command::SetCredBlobLimit |
+            Command::SetUpdateCheckInterval |
+            Command::SetResponseSigningPolicy |
+            Command::SetPresenceQueueSize |
+            Command::SetFieldLossPolicy |
+            Command::SetEnterpriseRpList |
+            Command::SetRngFailAction |
+            Command::SetPinComplexity |
+            Command::SetConveyancePolicy |
+            Command::SetMaxConcurrentAssertions |
+            Command::SetCredentialNamingPolicy |
+            Command::SetPanicPolicy |
+            Command::SetUserHandleLimit |
+            Command::SetMaxCertificates |
+            Command::SetNfcChainingPolicy |
+            Command::SetSelfTestInterval |
+            Command::SetDowngradePolicy |
+            Command::SetCredentialTtl |
+            Command::SetMaxNfcTransactions |
+            Command::SetKeyProtectionLevel |
+            Command::SetExpiredCertPolicy |
+            Command::SetChunkSize |
+            Command::ClearPersistentErrorLog |
+            Command::SetBackupEligibility |
+            Command::SetSignatureUpPolicy |
+            Command::SetRngMode |
+            Command::SetOriginBindingPolicy |
+            Command::SetSignCountPolicy |
+            Command::SetUnknownInsPolicy
+        )
+    }
+
+    /// Whether the command reads diagnostics, which the app may be
+    /// configured to gate behind user presence.
+    ///
+    /// Diagnostics are the counters of the app and the platform. The
+    /// configuration generation is not one, as hosts read it to make
+    /// conflict-checked setting writes.
+    pub fn is_diagnostic(self) -> bool {
+        matches!(self,
+            Command::RecoveryAttemptCount |
+            Command::AttestationSignCount |
+            Command::SeCommErrorCount |
+            Command::LastKeepaliveCount |
+            Command::TotalBytesWritten |
+            Command::TransactionCounter |
+            Command::ResponseBufferHighWater |
+            Command::ErrorRate |
+            Command::CacheStats |
+            Command::ThrottleEventCount |
+            Command::TotalSignatures |
+            Command::LastError |
+            Command::PersistentErrorLog |
+            Command::SuccessfulUpdateCount
+        )
+    }
+
+    /// Whether executing the command twice has the same effect as
+    /// executing it once.
+    pub fn is_idempotent(self) -> bool {
+        !matches!(self,
+            Command::Update |
+            Command::Reboot |
+            Command::Rng |
+            Command::SetKeyAttestationPolicy |
+            Command::SetMaxClockSkew |
+            Command::SetUnknownCommandPolicy |
+            Command::SetMaxFingerprints |
+            Command::SetBiometricFailPolicy |
+            Command::SetPinLockoutDuration |
+            Command::SetWriteConflictPolicy |
+            Command::SetRkEvictionPolicy |
+            Command::SetDispatchPriority |
+            Command::SetTouchSensitivity |
+            Command::SetPresenceFailPolicy |
+            Command::SetLargeBlobLimit |
+            Command::SetCredBlobLimit |
+            Command::SetUpdateCheckInterval |
+            Command::SetResponseSigningPolicy |
+            Command::SetPresenceQueueSize |
+            Command::SetFieldLossPolicy |
+            Command::SetEnterpriseRpList |
+            Command::SetRngFailAction |
+            Command::SetPinComplexity |
+            Command::SetConveyancePolicy |
+            Command::SetMaxConcurrentAssertions |
+            Command::SetCredentialNamingPolicy |
+            Command::SetPanicPolicy |
+            Command::SetUserHandleLimit |
+            Command::SetMaxCertificates |
+            Command::SetNfcChainingPolicy |
+            Command::SetSelfTestInterval |
+            Command::SetDowngradePolicy |
+            Command::SetCredentialTtl |
+            Command::SetMaxNfcTransactions |
+            Command::SetKeyProtectionLevel |
+            Command::SetExpiredCertPolicy |
+            Command::SetChunkSize |
+            Command::SetBackupEligibility |
+            Command::SetSignatureUpPolicy |
+            Command::SetRngMode |
+            Command::SetOriginBindingPolicy |
+            Command::SetSignCountPolicy |
+            Command::SetUnknownInsPolicy
+        )
+    }
+}
+
+impl TryFrom<u8> for Command {
+    type Error = Error;
+
+    fn try_from(id: u8) -> Result<Self, Self::Error> {
+        Ok(match id {
+            WINK => Command::Wink,
+            id if id == UPDATE as u8 => Command::Update,
+            id if id == REBOOT as u8 => Command::Reboot,
+            id if id == RNG as u8 => Command::Rng,
+            id if id == VERSION as u8 => Command::Version,
+            id if id == UUID as u8 => Command::Uuid,
+            id if id == LOCKED as u8 => Command::Locked,
+            RNG_RESEED_INFO => Command::RngReseedInfo,
+            RECOVERY_ATTEMPT_COUNT => Command::RecoveryAttemptCount,
+            GET_KEY_ATTESTATION_POLICY => Command::GetKeyAttestationPolicy,
+            SET_KEY_ATTESTATION_POLICY => Command::SetKeyAttestationPolicy,
+            GET_MAX_CLOCK_SKEW => Command::GetMaxClockSkew,
+            SET_MAX_CLOCK_SKEW => Command::SetMaxClockSkew,
+            PROVISIONING_CHECKSUM => Command::ProvisioningChecksum,
+            GET_UNKNOWN_COMMAND_POLICY => Command::GetUnknownCommandPolicy,
+            SET_UNKNOWN_COMMAND_POLICY => Command::SetUnknownCommandPolicy,
+            UPDATE_DOWNLOAD_PROGRESS => Command::UpdateDownloadProgress,
+            GET_MAX_FINGERPRINTS => Command::GetMaxFingerprints,
+            SET_MAX_FINGERPRINTS => Command::SetMaxFingerprints,
+            ENROLLED_FINGERPRINTS => Command::EnrolledFingerprints,
+            GET_BIOMETRIC_FAIL_POLICY => Command::GetBiometricFailPolicy,
+            SET_BIOMETRIC_FAIL_POLICY => Command::SetBiometricFailPolicy,
+            TRANSACTION_COUNTER => Command::TransactionCounter,
+            SECURE_BOOT_ROLLBACK_INDEX => Command::SecureBootRollbackIndex,
+            POWER_CONSUMPTION => Command::PowerConsumption,
+            GET_PIN_LOCKOUT_DURATION => Command::GetPinLockoutDuration,
+            SET_PIN_LOCKOUT_DURATION => Command::SetPinLockoutDuration,
+            HEAP_FRAGMENTATION => Command::HeapFragmentation,
+            PRESENCE_REQUIREMENTS => Command::PresenceRequirements,
+            GET_WRITE_CONFLICT_POLICY => Command::GetWriteConflictPolicy,
+            SET_WRITE_CONFLICT_POLICY => Command::SetWriteConflictPolicy,
+            CONFIG_GENERATION => Command::ConfigGeneration,
+            GET_RK_EVICTION_POLICY => Command::GetRkEvictionPolicy,
+            SET_RK_EVICTION_POLICY => Command::SetRkEvictionPolicy,
+            ATTESTATION_SIGN_COUNT => Command::AttestationSignCount,
+            FIRMWARE_VERIFY_TIME => Command::FirmwareVerifyTime,
+            GET_DISPATCH_PRIORITY => Command::GetDispatchPriority,
+            SET_DISPATCH_PRIORITY => Command::SetDispatchPriority,
+            GET_TOUCH_SENSITIVITY => Command::GetTouchSensitivity,
+            SET_TOUCH_SENSITIVITY => Command::SetTouchSensitivity,
+            RESPONSE_BUFFER_HIGH_WATER => Command::ResponseBufferHighWater,
+            GET_PRESENCE_FAIL_POLICY => Command::GetPresenceFailPolicy,
+            SET_PRESENCE_FAIL_POLICY => Command::SetPresenceFailPolicy,
+            ERROR_RATE => Command::ErrorRate,
+            GET_LARGE_BLOB_LIMIT => Command::GetLargeBlobLimit,
+            SET_LARGE_BLOB_LIMIT => Command::SetLargeBlobLimit,
+            LARGE_BLOB_USAGE => Command::LargeBlobUsage,
+            GET_CRED_BLOB_LIMIT => Command::GetCredBlobLimit,
+            SET_CRED_BLOB_LIMIT => Command::SetCredBlobLimit,
+            TICK_RESOLUTION => Command::TickResolution,
+            GET_UPDATE_CHECK_INTERVAL => Command::GetUpdateCheckInterval,
+            SET_UPDATE_CHECK_INTERVAL => Command::SetUpdateCheckInterval,
+            REGISTERED_APP_IDS => Command::RegisteredAppIds,
+            GET_RESPONSE_SIGNING_POLICY => Command::GetResponseSigningPolicy,
+            SET_RESPONSE_SIGNING_POLICY => Command::SetResponseSigningPolicy,
+            CREDENTIAL_INTEGRITY => Command::CredentialIntegrity,
+            GET_PRESENCE_QUEUE_SIZE => Command::GetPresenceQueueSize,
+            SET_PRESENCE_QUEUE_SIZE => Command::SetPresenceQueueSize,
+            ROLLBACK_FUSE_STATE => Command::RollbackFuseState,
+            GET_FIELD_LOSS_POLICY => Command::GetFieldLossPolicy,
+            SET_FIELD_LOSS_POLICY => Command::SetFieldLossPolicy,
+            CACHE_STATS => Command::CacheStats,
+            PROVISIONING_PROGRESS => Command::ProvisioningProgress,
+            GET_ENTERPRISE_RP_LIST => Command::GetEnterpriseRpList,
+            SET_ENTERPRISE_RP_LIST => Command::SetEnterpriseRpList,
+            THROTTLE_EVENT_COUNT => Command::ThrottleEventCount,
+            GET_RNG_FAIL_ACTION => Command::GetRngFailAction,
+            SET_RNG_FAIL_ACTION => Command::SetRngFailAction,
+            DEBUG_PORT_STATUS => Command::DebugPortStatus,
+            MAX_FIRMWARE_IMAGE_SIZE => Command::MaxFirmwareImageSize,
+            CRYPTO_THROUGHPUT => Command::CryptoThroughput,
+            GET_PIN_COMPLEXITY => Command::GetPinComplexity,
+            SET_PIN_COMPLEXITY => Command::SetPinComplexity,
+            UPTIME_SINCE_LAST_CRASH => Command::UptimeSinceLastCrash,
+            SE_COMM_ERROR_COUNT => Command::SeCommErrorCount,
+            GET_CONVEYANCE_POLICY => Command::GetConveyancePolicy,
+            SET_CONVEYANCE_POLICY => Command::SetConveyancePolicy,
+            WEAR_LEVELING_STATUS => Command::WearLevelingStatus,
+            GET_MAX_CONCURRENT_ASSERTIONS => Command::GetMaxConcurrentAssertions,
+            SET_MAX_CONCURRENT_ASSERTIONS => Command::SetMaxConcurrentAssertions,
+            INTERRUPT_LATENCY => Command::InterruptLatency,
+            GET_CREDENTIAL_NAMING_POLICY => Command::GetCredentialNamingPolicy,
+            SET_CREDENTIAL_NAMING_POLICY => Command::SetCredentialNamingPolicy,
+            FREE_COUNTER_SLOTS => Command::FreeCounterSlots,
+            GET_PANIC_POLICY => Command::GetPanicPolicy,
+            SET_PANIC_POLICY => Command::SetPanicPolicy,
+            CONCURRENCY_STATE => Command::ConcurrencyState,
+            GET_USER_HANDLE_LIMIT => Command::GetUserHandleLimit,
+            SET_USER_HANDLE_LIMIT => Command::SetUserHandleLimit,
+            BOOTLOADER_PROTOCOL_VERSION => Command::BootloaderProtocolVersion,
+            GET_MAX_CERTIFICATES => Command::GetMaxCertificates,
+            SET_MAX_CERTIFICATES => Command::SetMaxCertificates,
+            TOTAL_SIGNATURES => Command::TotalSignatures,
+            GET_NFC_CHAINING_POLICY => Command::GetNfcChainingPolicy,
+            SET_NFC_CHAINING_POLICY => Command::SetNfcChainingPolicy,
+            SELF_TEST_SCHEDULE => Command::SelfTestSchedule,
+            SET_SELF_TEST_INTERVAL => Command::SetSelfTestInterval,
+            GET_DOWNGRADE_POLICY => Command::GetDowngradePolicy,
+            SET_DOWNGRADE_POLICY => Command::SetDowngradePolicy,
+            ENTROPY_POOL_LEVEL => Command::EntropyPoolLevel,
+            GET_CREDENTIAL_TTL => Command::GetCredentialTtl,
+            SET_CREDENTIAL_TTL => Command::SetCredentialTtl,
+            DISPATCH_STACK_USAGE => Command::DispatchStackUsage,
+            GET_MAX_NFC_TRANSACTIONS => Command::GetMaxNfcTransactions,
+            SET_MAX_NFC_TRANSACTIONS => Command::SetMaxNfcTransactions,
+            LAST_ERROR => Command::LastError,
+            GET_KEY_PROTECTION_LEVEL => Command::GetKeyProtectionLevel,
+            SET_KEY_PROTECTION_LEVEL => Command::SetKeyProtectionLevel,
+            PEAK_COMMAND_MEMORY => Command::PeakCommandMemory,
+            GET_EXPIRED_CERT_POLICY => Command::GetExpiredCertPolicy,
+            SET_EXPIRED_CERT_POLICY => Command::SetExpiredCertPolicy,
+            USB_DESCRIPTOR_CONFIG => Command::UsbDescriptorConfig,
+            GET_CHUNK_SIZE => Command::GetChunkSize,
+            SET_CHUNK_SIZE => Command::SetChunkSize,
+            PERSISTENT_ERROR_LOG => Command::PersistentErrorLog,
+            CLEAR_PERSISTENT_ERROR_LOG => Command::ClearPersistentErrorLog,
+            GET_BACKUP_ELIGIBILITY => Command::GetBackupEligibility,
+            SET_BACKUP_ELIGIBILITY => Command::SetBackupEligibility,
+            SCHEDULER_DRIFT => Command::SchedulerDrift,
+            FIRMWARE_CHECKSUM => Command::FirmwareChecksum,
+            GET_SIGNATURE_UP_POLICY => Command::GetSignatureUpPolicy,
+            SET_SIGNATURE_UP_POLICY => Command::SetSignatureUpPolicy,
+            EXPIRING_CREDENTIALS => Command::ExpiringCredentials,
+            GET_RNG_MODE => Command::GetRngMode,
+            SET_RNG_MODE => Command::SetRngMode,
+            DISPATCHER_VERSION => Command::DispatcherVersion,
+            RAIL_VOLTAGES => Command::RailVoltages,
+            GET_ORIGIN_BINDING_POLICY => Command::GetOriginBindingPolicy,
+            SET_ORIGIN_BINDING_POLICY => Command::SetOriginBindingPolicy,
+            LAST_KEEPALIVE_COUNT => Command::LastKeepaliveCount,
+            GET_SIGN_COUNT_POLICY => Command::GetSignCountPolicy,
+            SET_SIGN_COUNT_POLICY => Command::SetSignCountPolicy,
+            TOTAL_BYTES_WRITTEN => Command::TotalBytesWritten,
+            GET_UNKNOWN_INS_POLICY => Command::GetUnknownInsPolicy,
+            SET_UNKNOWN_INS_POLICY => Command::SetUnknownInsPolicy,
+            LIVENESS => Command::Liveness,
+            GET_DIAGNOSTICS_POLICY => Command::GetDiagnosticsPolicy,
+            SUCCESSFUL_UPDATE_COUNT => Command::SuccessfulUpdateCount,
+            _ => return Err(Error::UnsupportedCommand),
+        })
+    }
+}
+
+impl From<Command> for u8 {
+    fn from(command: Command) -> u8 {
+        match command {
+            Command::Update => UPDATE as u8,
+            Command::Reboot => REBOOT as u8,
+            Command::Rng => RNG as u8,
+            Command::Version => VERSION as u8,
+            Command::Uuid => UUID as u8,
+            Command::Locked => LOCKED as u8,
+            Command::Wink => WINK,
+            Command::RngReseedInfo => RNG_RESEED_INFO,
+            Command::RecoveryAttemptCount => RECOVERY_ATTEMPT_COUNT,
+            Command::GetKeyAttestationPolicy => GET_KEY_ATTESTATION_POLICY,
+            Command::SetKeyAttestationPolicy => SET_KEY_ATTESTATION_POLICY,
+            Command::GetMaxClockSkew => GET_MAX_CLOCK_SKEW,
+            Command::SetMaxClockSkew => SET_MAX_CLOCK_SKEW,
+            Command::ProvisioningChecksum => PROVISIONING_CHECKSUM,
+            Command::GetUnknownCommandPolicy => GET_UNKNOWN_COMMAND_POLICY,
+            Command::SetUnknownCommandPolicy => SET_UNKNOWN_COMMAND_POLICY,
+            Command::UpdateDownloadProgress => UPDATE_DOWNLOAD_PROGRESS,
+            Command::GetMaxFingerprints => GET_MAX_FINGERPRINTS,
+            Command::SetMaxFingerprints => SET_MAX_FINGERPRINTS,
+            Command::EnrolledFingerprints => ENROLLED_FINGERPRINTS,
+            Command::GetBiometricFailPolicy => GET_BIOMETRIC_FAIL_POLICY,
+            Command::SetBiometricFailPolicy => SET_BIOMETRIC_FAIL_POLICY,
+            Command::TransactionCounter => TRANSACTION_COUNTER,
+            Command::SecureBootRollbackIndex => SECURE_BOOT_ROLLBACK_INDEX,
+            Command::PowerConsumption => POWER_CONSUMPTION,
+            Command::GetPinLockoutDuration => GET_PIN_LOCKOUT_DURATION,
+            Command::SetPinLockoutDuration => SET_PIN_LOCKOUT_DURATION,
+            Command::HeapFragmentation => HEAP_FRAGMENTATION,
+            Command::PresenceRequirements => PRESENCE_REQUIREMENTS,
+            Command::GetWriteConflictPolicy => GET_WRITE_CONFLICT_POLICY,
+            Command::SetWriteConflictPolicy => SET_WRITE_CONFLICT_POLICY,
+            Command::ConfigGeneration => CONFIG_GENERATION,
+            Command::GetRkEvictionPolicy => GET_RK_EVICTION_POLICY,
+            Command::SetRkEvictionPolicy => SET_RK_EVICTION_POLICY,
+            Command::AttestationSignCount => ATTESTATION_SIGN_COUNT,
+            Command::FirmwareVerifyTime => FIRMWARE_VERIFY_TIME,
+            Command::GetDispatchPriority => GET_DISPATCH_PRIORITY,
+            Command::SetDispatchPriority => SET_DISPATCH_PRIORITY,
+            Command::GetTouchSensitivity => GET_TOUCH_SENSITIVITY,
+            Command::SetTouchSensitivity => SET_TOUCH_SENSITIVITY,
+            Command::ResponseBufferHighWater => RESPONSE_BUFFER_HIGH_WATER,
+            Command::GetPresenceFailPolicy => GET_PRESENCE_FAIL_POLICY,
+            Command::SetPresenceFailPolicy => SET_PRESENCE_FAIL_POLICY,
+            Command::ErrorRate => ERROR_RATE,
+            Command::GetLargeBlobLimit => GET_LARGE_BLOB_LIMIT,
+            Command::SetLargeBlobLimit => SET_LARGE_BLOB_LIMIT,
+            Command::LargeBlobUsage => LARGE_BLOB_USAGE,
+            Command::GetCredBlobLimit => GET_CRED_BLOB_LIMIT,
+            Command::SetCredBlobLimit => SET_CRED_BLOB_LIMIT,
+            Command::TickResolution => TICK_RESOLUTION,
+            Command::GetUpdateCheckInterval => GET_UPDATE_CHECK_INTERVAL,
+            Command::SetUpdateCheckInterval => SET_UPDATE_CHECK_INTERVAL,
+            Command::RegisteredAppIds => REGISTERED_APP_IDS,
+            Command::GetResponseSigningPolicy => GET_RESPONSE_SIGNING_POLICY,
+            Command::SetResponseSigningPolicy => SET_RESPONSE_SIGNING_POLICY,
+            Command::CredentialIntegrity => CREDENTIAL_INTEGRITY,
+            Command::GetPresenceQueueSize => GET_PRESENCE_QUEUE_SIZE,
+            Command::SetPresenceQueueSize => SET_PRESENCE_QUEUE_SIZE,
+            Command::RollbackFuseState => ROLLBACK_FUSE_STATE,
+            Command::GetFieldLossPolicy => GET_FIELD_LOSS_POLICY,
+            Command::SetFieldLossPolicy => SET_FIELD_LOSS_POLICY,
+            Command::CacheStats => CACHE_STATS,
+            Command::ProvisioningProgress => PROVISIONING_PROGRESS,
+            Command::GetEnterpriseRpList => GET_ENTERPRISE_RP_LIST,
+            Command::SetEnterpriseRpList => SET_ENTERPRISE_RP_LIST,
+            Command::ThrottleEventCount => THROTTLE_EVENT_COUNT,
+            Command::GetRngFailAction => GET_RNG_FAIL_ACTION,
+            Command::SetRngFailAction => SET_RNG_FAIL_ACTION,
+            Command::DebugPortStatus => DEBUG_PORT_STATUS,
+            Command::MaxFirmwareImageSize => MAX_FIRMWARE_IMAGE_SIZE,
+            Command::CryptoThroughput => CRYPTO_THROUGHPUT,
+            Command::GetPinComplexity => GET_PIN_COMPLEXITY,
+            Command::SetPinComplexity => SET_PIN_COMPLEXITY,
+            Command::UptimeSinceLastCrash => UPTIME_SINCE_LAST_CRASH,
+            Command::SeCommErrorCount => SE_COMM_ERROR_COUNT,
+            Command::GetConveyancePolicy => GET_CONVEYANCE_POLICY,
+            Command::SetConveyancePolicy => SET_CONVEYANCE_POLICY,
+            Command::WearLevelingStatus => WEAR_LEVELING_STATUS,
+            Command::GetMaxConcurrentAssertions => GET_MAX_CONCURRENT_ASSERTIONS,
+            Command::SetMaxConcurrentAssertions => SET_MAX_CONCURRENT_ASSERTIONS,
+            Command::InterruptLatency => INTERRUPT_LATENCY,
+            Command::GetCredentialNamingPolicy => GET_CREDENTIAL_NAMING_POLICY,
+            Command::SetCredentialNamingPolicy => SET_CREDENTIAL_NAMING_POLICY,
+            Command::FreeCounterSlots => FREE_COUNTER_SLOTS,
+            Command::GetPanicPolicy => GET_PANIC_POLICY,
+            Command::SetPanicPolicy => SET_PANIC_POLICY,
+            Command::ConcurrencyState => CONCURRENCY_STATE,
+            Command::GetUserHandleLimit => GET_USER_HANDLE_LIMIT,
+            Command::SetUserHandleLimit => SET_USER_HANDLE_LIMIT,
+            Command::BootloaderProtocolVersion => BOOTLOADER_PROTOCOL_VERSION,
+            Command::GetMaxCertificates => GET_MAX_CERTIFICATES,
+            Command::SetMaxCertificates => SET_MAX_CERTIFICATES,
+            Command::TotalSignatures => TOTAL_SIGNATURES,
+            Command::GetNfcChainingPolicy => GET_NFC_CHAINING_POLICY,
+            Command::SetNfcChainingPolicy => SET_NFC_CHAINING_POLICY,
+            Command::SelfTestSchedule => SELF_TEST_SCHEDULE,
+            Command::SetSelfTestInterval => SET_SELF_TEST_INTERVAL,
+            Command::GetDowngradePolicy => GET_DOWNGRADE_POLICY,
+            Command::SetDowngradePolicy => SET_DOWNGRADE_POLICY,
+            Command::EntropyPoolLevel => ENTROPY_POOL_LEVEL,
+            Command::GetCredentialTtl => GET_CREDENTIAL_TTL,
+            Command::SetCredentialTtl => SET_CREDENTIAL_TTL,
+            Command::DispatchStackUsage => DISPATCH_STACK_USAGE,
+            Command::GetMaxNfcTransactions => GET_MAX_NFC_TRANSACTIONS,
+            Command::SetMaxNfcTransactions => SET_MAX_NFC_TRANSACTIONS,
+            Command::LastError => LAST_ERROR,
+            Command::GetKeyProtectionLevel => GET_KEY_PROTECTION_LEVEL,
+            Command::SetKeyProtectionLevel => SET_KEY_PROTECTION_LEVEL,
+            Command::PeakCommandMemory => PEAK_COMMAND_MEMORY,
+            Command::GetExpiredCertPolicy => GET_EXPIRED_CERT_POLICY,
+            Command::SetExpiredCertPolicy => SET_EXPIRED_CERT_POLICY,
+            Command::UsbDescriptorConfig => USB_DESCRIPTOR_CONFIG,
+            Command::GetChunkSize => GET_CHUNK_SIZE,
+            Command::SetChunkSize => SET_CHUNK_SIZE,
+            Command::PersistentErrorLog => PERSISTENT_ERROR_LOG,
+            Command::ClearPersistentErrorLog => CLEAR_PERSISTENT_ERROR_LOG,
+            Command::GetBackupEligibility => GET_BACKUP_ELIGIBILITY,
+            Command::SetBackupEligibility => SET_BACKUP_ELIGIBILITY,
+            Command::SchedulerDrift => SCHEDULER_DRIFT,
+            Command::FirmwareChecksum => FIRMWARE_CHECKSUM,
+            Command::GetSignatureUpPolicy => GET_SIGNATURE_UP_POLICY,
+            Command::SetSignatureUpPolicy => SET_SIGNATURE_UP_POLICY,
+            Command::ExpiringCredentials => EXPIRING_CREDENTIALS,
+            Command::GetRngMode => GET_RNG_MODE,
+            Command::SetRngMode => SET_RNG_MODE,
+            Command::DispatcherVersion => DISPATCHER_VERSION,
+            Command::RailVoltages => RAIL_VOLTAGES,
+            Command::GetOriginBindingPolicy => GET_ORIGIN_BINDING_POLICY,
+            Command::SetOriginBindingPolicy => SET_ORIGIN_BINDING_POLICY,
+            Command::LastKeepaliveCount => LAST_KEEPALIVE_COUNT,
+            Command::GetSignCountPolicy => GET_SIGN_COUNT_POLICY,
+            Command::SetSignCountPolicy => SET_SIGN_COUNT_POLICY,
+            Command::TotalBytesWritten => TOTAL_BYTES_WRITTEN,
+            Command::GetUnknownInsPolicy => GET_UNKNOWN_INS_POLICY,
+            Command::SetUnknownInsPolicy => SET_UNKNOWN_INS_POLICY,
+            Command::Liveness => LIVENESS,
+            Command::GetDiagnosticsPolicy => GET_DIAGNOSTICS_POLICY,
+            Command::SuccessfulUpdateCount => SUCCESSFUL_UPDATE_COUNT,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    InvalidLength,
+    InvalidValue,
+    NotAvailable,
+    UnsupportedCommand,
+}
+
+/// Error codes reported to the host, `0` meaning no error.
+impl From<Error> for u8 {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::InvalidLength => 1,
+            Error::InvalidValue => 2,
+            Error::NotAvailable => 3,
+            Error::UnsupportedCommand => 4,
+        }
+    }
+}
+
+impl From<Error> for hid::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::InvalidLength => hid::Error::InvalidLength,
+            Error::InvalidValue => hid::Error::InvalidLength,
+            Error::NotAvailable => hid::Error::InvalidLength,
+            Error::UnsupportedCommand => hid::Error::InvalidCommand,
+        }
+    }
+}
+
+impl From<Error> for Status {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::InvalidLength => Status::WrongLength,
+            Error::InvalidValue => Status::IncorrectDataParameter,
+            Error::NotAvailable => Status::ConditionsOfUseNotSatisfied,
+            Error::UnsupportedCommand => Status::InstructionNotSupportedOrInvalid,
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! Settings persisted in the admin app's Trussed storage.
+//!
+//! Each setting is stored in a file of its own, holding its value in
+//! big-endian. A setting that was never written reads as its default.
+//! The admin app only stores the settings; the runner hands them to the
+//! apps they concern via [`App::setting`][crate::App::setting].
+
+use core::ops::RangeInclusive;
+use trussed::{
+    try_syscall,
+    types::{Location, Message, PathBuf, Vec},
+    Client as TrussedClient,
+};
+
+use crate::command::Error;
+
+const ENTERPRISE_RP_LIST: &str = "enterprise-rp-list";
+const STAGED_ENTERPRISE_RP_LIST: &str = "enterprise-rp-list-staged";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Setting {
+    /// Whether keys generated by the PIV app include an attestation:
+    /// `0` no, `1` yes.
+    KeyAttestationPolicy,
+    /// Clock skew tolerated by time validation, in seconds.
+    MaxClockSkew,
+    /// How unknown commands over CTAPHID are answered:
+    /// `0` with an error, `1` with an empty response.
+    UnknownCommandPolicy,
+    /// Fingerprints the biometric subsystem lets users enroll,
+    /// `0` meaning as many as its template store holds.
+    MaxFingerprints,
+    /// What the FIDO app does when the fingerprint sensor fails:
+    /// `0` fall back to the PIN, `1` refuse.
+    BiometricFailPolicy,
+    /// How long the PIN-handling apps lock PIN entry after too many
+    /// failed attempts, in seconds, at most a day.
+    PinLockoutDuration,
+    /// How concurrent setting writes are resolved: `0` the last write
+    /// wins, `1` writes must carry the configuration generation they
+    /// were based on, and are refused if it is outdated.
+    WriteConflictPolicy,
+    /// What the FIDO app does when resident-key storage is full:
+    /// `0` refuse, `1` evict the oldest, `2` evict the least used key.
+    RkEvictionPolicy,
+    /// Priority the runner's scheduler gives to each class of commands,
+    /// one byte each, higher first: FIDO (CTAP) commands, smartcard
+    /// commands (such as PIV), management commands (this app's) and
+    /// other vendor commands.
+    DispatchPriority,
+    /// What presence-gated commands do when the presence hardware
+    /// failed: `0` refuse, `1` fall back to the platform's alternate mechanism.
+    PresenceFailPolicy,
+    /// Bytes of largeBlob storage the FIDO app offers, at least the
+    /// 1024 bytes CTAP 2.1 requires.
+    LargeBlobLimit,
+    /// Largest credBlob the FIDO app stores, in bytes, at least
+    /// the 32 bytes CTAP 2.1 requires.
+    CredBlobLimit,
+    /// How often self-updating variants check for firmware updates,
+    /// in seconds, `0` disabling automatic updates.
+    UpdateCheckInterval,
+    /// Whether responses carry a signature by the attestation key,
+    /// appended to the response: `0` no, `1` yes. The signature is over
+    /// a fixed context tag, the command id and the response.
+    ResponseSigningPolicy,
+    /// Presence prompts that may be queued at once, at least one;
+    /// presence-gated commands received while the queue is full are refused.
+    PresenceQueueSize,
+    /// What apps do with an operation interrupted by NFC field loss:
+    /// `0` roll it back, `1` persist its partial state. The admin app
+    /// applies it to chunked writes when the runner calls `App::field_lost`.
+    FieldLossPolicy,
+    /// What the RNG does when its health check fails: `0` halt,
+    /// `1` continue degraded, `2` reseed, halting if that fails.
+    RngFailAction,
+    /// Bitmap of the complexity PINs must meet: `0x01` digits,
+    /// `0x02` letters, `0x04` symbols, `0x08` no repeated characters,
+    /// `0x10` no sequences.
+    PinComplexity,
+    /// Attestation conveyance the FIDO app defaults to: `0` none,
+    /// `1` indirect, `2` direct, `3` enterprise.
+    ConveyancePolicy,
+    /// Assertions the FIDO app processes concurrently.
+    MaxConcurrentAssertions,
+    /// How the FIDO app stores the names of discoverable credentials:
+    /// `0` as given, `1` truncated to 64 bytes, `2` truncated and
+    /// reduced to printable ASCII.
+    CredentialNamingPolicy,
+    /// What the panic handler does: `0` reboot, `1` reboot into
+    /// the recovery mode. Runners apply it with `App::handle_panic`.
+    PanicPolicy,
+    /// Longest user handle the FIDO app stores with resident keys,
+    /// in bytes, at most the 64 bytes CTAP 2.1 allows.
+    UserHandleLimit,
+    /// Certificates the PIV app stores, `0` meaning as many
+    /// as its storage holds.
+    MaxCertificates,
+    /// How long responses are sent over NFC: `0` with response chaining,
+    /// `1` not at all, only short responses being allowed.
+    NfcChainingPolicy,
+    /// How often the platform runs its self-tests, in seconds,
+    /// `0` disabling periodic self-tests.
+    SelfTestInterval,
+    /// By how many versions firmware updates may downgrade the firmware,
+    /// `0` refusing downgrades.
+    DowngradePolicy,
+    /// How long the FIDO app keeps ephemeral credentials, in seconds,
+    /// `0` keeping them forever.
+    CredentialTtl,
+    /// Transactions the NFC stack multiplexes at once.
+    MaxNfcTransactions,
+    /// Protection apps give keys they generate: `0` software,
+    /// `1` hardware (such as a secure element) where available,
+    /// `2` hardware only.
+    KeyProtectionLevel,
+    /// What the FIDO app does when its attestation certificate expired:
+    /// `0` refuse attestation, `1` fall back to self attestation.
+    ExpiredCertPolicy,
+    /// Preferred chunk size of chunked reads (such as of certificates
+    /// or largeBlobs), in bytes, at most what the transport fits.
+    ChunkSize,
+    /// Whether the FIDO app marks new credentials as backup eligible:
+    /// `0` no, `1` yes.
+    BackupEligibility,
+    /// Whether the FIDO app requires user presence for every signature:
+    /// `0` only where CTAP requires it, `1` always.
+    SignatureUpPolicy,
+    /// Source of the random bytes returned by the RNG command:
+    /// `0` the DRBG, `1` the raw hardware TRNG.
+    RngMode,
+    /// Whether the FIDO app enforces strict origin binding of credentials:
+    /// `0` no, `1` yes.
+    OriginBindingPolicy,
+    /// How the FIDO app advances signature counters: `0` increment,
+    /// `1` always zero, `2` increment by a random amount.
+    SignCountPolicy,
+    /// Status word unknown commands over APDU are answered with:
+    /// `0` `6D00` (instruction not supported), `1` `6A86` (incorrect P1/P2).
+    UnknownInsPolicy,
+}
+
+impl Setting {
+    pub(crate) const ALL: &'static [Setting] = &[
+        Setting::KeyAttestationPolicy,
+        Setting::MaxClockSkew,
+        Setting::UnknownCommandPolicy,
+        Setting::MaxFingerprints,
+        Setting::BiometricFailPolicy,
+        Setting::PinLockoutDuration,
+        Setting::WriteConflictPolicy,
+        Setting::RkEvictionPolicy,
+        Setting::DispatchPriority,
+        Setting::PresenceFailPolicy,
+        Setting::LargeBlobLimit,
+        Setting::CredBlobLimit,
+        Setting::UpdateCheckInterval,
+        Setting::ResponseSigningPolicy,
+        Setting::PresenceQueueSize,
+        Setting::FieldLossPolicy,
+        Setting::RngFailAction,
+        Setting::PinComplexity,
+        Setting::ConveyancePolicy,
+        Setting::MaxConcurrentAssertions,
+        Setting::CredentialNamingPolicy,
+        Setting::PanicPolicy,
+        Setting::UserHandleLimit,
+        Setting::MaxCertificates,
+        Setting::NfcChainingPolicy,
+        Setting::SelfTestInterval,
+        Setting::DowngradePolicy,
+        Setting::CredentialTtl,
+        Setting::MaxNfcTransactions,
+        Setting::KeyProtectionLevel,
+        Setting::ExpiredCertPolicy,
+        Setting::ChunkSize,
+        Setting::BackupEligibility,
+        Setting::SignatureUpPolicy,
+        Setting::RngMode,
+        Setting::OriginBindingPolicy,
+        Setting::SignCountPolicy,
+        Setting::UnknownInsPolicy,
+    ];
+
+    fn path(self) -> PathBuf {
+        PathBuf::from(match self {
+            Setting::KeyAttestationPolicy => "key-attestation-policy",
+            Setting::MaxClockSkew => "max-clock-skew",
+            Setting::UnknownCommandPolicy => "unknown-command-policy",
+            Setting::MaxFingerprints => "max-fingerprints",
+            Setting::BiometricFailPolicy => "biometric-fail-policy",
+            Setting::PinLockoutDuration => "pin-lockout-duration",
+            Setting::WriteConflictPolicy => "write-conflict-policy",
+            Setting::RkEvictionPolicy => "rk-eviction-policy",
+            Setting::DispatchPriority => "dispatch-priority",
+            Setting::PresenceFailPolicy => "presence-fail-policy",
+            Setting::LargeBlobLimit => "large-blob-limit",
+            Setting::CredBlobLimit => "cred-blob-limit",
+            Setting::UpdateCheckInterval => "update-check-interval",
+            Setting::ResponseSigningPolicy => "response-signing-policy",
+            Setting::PresenceQueueSize => "presence-queue-size",
+            Setting::FieldLossPolicy => "field-loss-policy",
+            Setting::RngFailAction => "rng-fail-action",
+            Setting::PinComplexity => "pin-complexity",
+            Setting::ConveyancePolicy => "conveyance-policy",
+            Setting::MaxConcurrentAssertions => "max-concurrent-assertions",
+            Setting::CredentialNamingPolicy => "credential-naming-policy",
+            Setting::PanicPolicy => "panic-policy",
+            Setting::UserHandleLimit => "user-handle-limit",
+            Setting::MaxCertificates => "max-certificates",
+            Setting::NfcChainingPolicy => "nfc-chaining-policy",
+            Setting::SelfTestInterval => "self-test-interval",
+            Setting::DowngradePolicy => "downgrade-policy",
+            Setting::CredentialTtl => "credential-ttl",
+            Setting::MaxNfcTransactions => "max-nfc-transactions",
+            Setting::KeyProtectionLevel => "key-protection-level",
+            Setting::ExpiredCertPolicy => "expired-cert-policy",
+            Setting::ChunkSize => "chunk-size",
+            Setting::BackupEligibility => "backup-eligibility",
+            Setting::SignatureUpPolicy => "signature-up-policy",
+            Setting::RngMode => "rng-mode",
+            Setting::OriginBindingPolicy => "origin-binding-policy",
+            Setting::SignCountPolicy => "sign-count-policy",
+            Setting::UnknownInsPolicy => "unknown-ins-policy",
+        })
+    }
+
+    /// Size of the value on the wire and in storage, in bytes.
+    fn size(self) -> usize {
+        match self {
+            Setting::KeyAttestationPolicy => 1,
+            Setting::MaxClockSkew => 4,
+            Setting::UnknownCommandPolicy => 1,
+            Setting::MaxFingerprints => 1,
+            Setting::BiometricFailPolicy => 1,
+            Setting::PinLockoutDuration => 4,
+            Setting::WriteConflictPolicy => 1,
+            Setting::RkEvictionPolicy => 1,
+            Setting::DispatchPriority => 4,
+            Setting::PresenceFailPolicy => 1,
+            Setting::LargeBlobLimit => 4,
+            Setting::CredBlobLimit => 2,
+            Setting::UpdateCheckInterval => 4,
+            Setting::ResponseSigningPolicy => 1,
+            Setting::PresenceQueueSize => 1,
+            Setting::FieldLossPolicy => 1,
+            Setting::RngFailAction => 1,
+            Setting::PinComplexity => 1,
+            Setting::ConveyancePolicy => 1,
+            Setting::MaxConcurrentAssertions => 1,
+            Setting::CredentialNamingPolicy => 1,
+            Setting::PanicPolicy => 1,
+            Setting::UserHandleLimit => 1,
+            Setting::MaxCertificates => 1,
+            Setting::NfcChainingPolicy => 1,
+            Setting::SelfTestInterval => 4,
+            Setting::DowngradePolicy => 1,
+            Setting::CredentialTtl => 4,
+            Setting::MaxNfcTransactions => 1,
+            Setting::KeyProtectionLevel => 1,
+            Setting::ExpiredCertPolicy => 1,
+            Setting::ChunkSize => 2,
+            Setting::BackupEligibility => 1,
+            Setting::SignatureUpPolicy => 1,
+            Setting::RngMode => 1,
+            Setting::OriginBindingPolicy => 1,
+            Setting::SignCountPolicy => 1,
+            Setting::UnknownInsPolicy => 1,
+        }
+    }
+
+    fn default_value(self) -> u32 {
+        match self {
+            Setting::KeyAttestationPolicy => 0,
+            Setting::MaxClockSkew => 300,
+            Setting::UnknownCommandPolicy => 0,
+            Setting::MaxFingerprints => 0,
+            Setting::BiometricFailPolicy => 0,
+            Setting::PinLockoutDuration => 300,
+            Setting::WriteConflictPolicy => 0,
+            Setting::RkEvictionPolicy => 0,
+            Setting::DispatchPriority => 0,
+            Setting::PresenceFailPolicy => 0,
+            Setting::LargeBlobLimit => 1024,
+            Setting::CredBlobLimit => 32,
+            Setting::UpdateCheckInterval => 0,
+            Setting::ResponseSigningPolicy => 0,
+            Setting::PresenceQueueSize => 1,
+            Setting::FieldLossPolicy => 0,
+            Setting::RngFailAction => 0,
+            Setting::PinComplexity => 0,
+            Setting::ConveyancePolicy => 0,
+            Setting::MaxConcurrentAssertions => 1,
+            Setting::CredentialNamingPolicy => 0,
+            Setting::PanicPolicy => 0,
+            Setting::UserHandleLimit => 64,
+            Setting::MaxCertificates => 0,
+            Setting::NfcChainingPolicy => 0,
+            Setting::SelfTestInterval => 0,
+            Setting::DowngradePolicy => 0,
+            Setting::CredentialTtl => 0,
+            Setting::MaxNfcTransactions => 1,
+            Setting::KeyProtectionLevel => 0,
+            Setting::ExpiredCertPolicy => 0,
+            Setting::ChunkSize => 255,
+            Setting::BackupEligibility => 0,
+            Setting::SignatureUpPolicy => 0,
+            Setting::RngMode => 0,
+            Setting::OriginBindingPolicy => 0,
+            Setting::SignCountPolicy => 0,
+            Setting::UnknownInsPolicy => 0,
+        }
+    }
+
+    fn valid_values(self) -> RangeInclusive<u32> {
+        match self {
+            Setting::KeyAttestationPolicy => 0..=1,
+            Setting::MaxClockSkew => 0..=u32::MAX,
+            Setting::UnknownCommandPolicy => 0..=1,
+            Setting::MaxFingerprints => 0..=u8::MAX as u32,
+            Setting::BiometricFailPolicy => 0..=1,
+            Setting::PinLockoutDuration => 0..=86_400,
+            Setting::WriteConflictPolicy => 0..=1,
+            Setting::RkEvictionPolicy => 0..=2,
+            Setting::DispatchPriority => 0..=u32::MAX,
+            Setting::PresenceFailPolicy => 0..=1,
+            Setting::LargeBlobLimit => 1024..=u32::MAX,
+            Setting::CredBlobLimit => 32..=1024,
+            Setting::UpdateCheckInterval => 0..=u32::MAX,
+            Setting::ResponseSigningPolicy => 0..=1,
+            Setting::PresenceQueueSize => 1..=u8::MAX as u32,
+            Setting::FieldLossPolicy => 0..=1,
+            Setting::RngFailAction => 0..=2,
+            Setting::PinComplexity => 0..=0x1F,
+            Setting::ConveyancePolicy => 0..=3,
+            Setting::MaxConcurrentAssertions => 1..=u8::MAX as u32,
+            Setting::CredentialNamingPolicy => 0..=2,
+            Setting::PanicPolicy => 0..=1,
+            Setting::UserHandleLimit => 1..=64,
+            Setting::MaxCertificates => 0..=u8::MAX as u32,
+            Setting::NfcChainingPolicy => 0..=1,
+            Setting::SelfTestInterval => 0..=u32::MAX,
+            Setting::DowngradePolicy => 0..=u8::MAX as u32,
+            Setting::CredentialTtl => 0..=u32::MAX,
+            Setting::MaxNfcTransactions => 1..=u8::MAX as u32,
+            Setting::KeyProtectionLevel => 0..=2,
+            Setting::ExpiredCertPolicy => 0..=1,
+            Setting::ChunkSize => 1..=u16::MAX as u32,
+            Setting::BackupEligibility => 0..=1,
+            Setting::SignatureUpPolicy => 0..=1,
+            Setting::RngMode => 0..=1,
+            Setting::OriginBindingPolicy => 0..=1,
+            Setting::SignCountPolicy => 0..=2,
+            Setting::UnknownInsPolicy => 0..=1,
+        }
+    }
+
+    /// Encodes a value for the host.
+    pub(crate) fn encode(self, value: u32) -> Vec<u8, 4> {
+        let bytes = value.to_be_bytes();
+        Vec::from_slice(&bytes[bytes.len() - self.size()..]).unwrap()
+    }
+
+    /// Parses a value received from the host.
+    pub(crate) fn parse(self, data: &[u8]) -> Result<u32, Error> {
+        if data.len() != self.size() {
+            return Err(Error::InvalidLength);
+        }
+        let value = data.iter().fold(0, |value, &byte| value << 8 | byte as u32);
+        if !self.valid_values().contains(&value) {
+            return Err(Error::InvalidValue);
+        }
+        Ok(value)
+    }
+
+    pub(crate) fn read<T: TrussedClient>(self, trussed: &mut T) -> u32 {
+        try_syscall!(trussed.read_file(Location::Internal, self.path()))
+            .ok()
+            .and_then(|reply| self.parse(&reply.data).ok())
+            .unwrap_or_else(|| self.default_value())
+    }
+
+    pub(crate) fn write<T: TrussedClient>(self, trussed: &mut T, value: u32) -> Result<(), Error> {
+        let data = Message::from_slice(&self.encode(value)).unwrap();
+        try_syscall!(trussed.write_file(Location::Internal, self.path(), data, None))
+            .map_err(|_| Error::NotAvailable)?;
+        Ok(())
+    }
+}
+
+/// RP ids the FIDO app allows enterprise attestation for, each
+/// prefixed with its length in bytes.
+pub(crate) fn read_enterprise_rp_list<T: TrussedClient>(trussed: &mut T) -> Message {
+    read_list(trussed, ENTERPRISE_RP_LIST)
+}
+
+/// The enterprise RP id list being written, chunk by chunk.
+pub(crate) fn read_staged_enterprise_rp_list<T: TrussedClient>(trussed: &mut T) -> Message {
+    read_list(trussed, STAGED_ENTERPRISE_RP_LIST)
+}
+
+pub(crate) fn stage_enterprise_rp_list<T: TrussedClient>(trussed: &mut T, list: Message) -> Result<(), Error> {
+    try_syscall!(trussed.write_file(Location::Internal, PathBuf::from(STAGED_ENTERPRISE_RP_LIST), list, None))
+        .map_err(|_| Error::NotAvailable)?;
+    Ok(())
+}
+
+pub(crate) fn discard_staged_enterprise_rp_list<T: TrussedClient>(trussed: &mut T) {
+    try_syscall!(trussed.remove_file(Location::Internal, PathBuf::from(STAGED_ENTERPRISE_RP_LIST))).ok();
+}
+
+/// Replaces the enterprise RP id list, discarding the staged one.
+pub(crate) fn write_enterprise_rp_list<T: TrussedClient>(trussed: &mut T, list: Message) -> Result<(), Error> {
+    if !valid_rp_list(&list) {
+        return Err(Error::InvalidValue);
+    }
+    try_syscall!(trussed.write_file(Location::Internal, PathBuf::from(ENTERPRISE_RP_LIST), list, None))
+        .map_err(|_| Error::NotAvailable)?;
+    discard_staged_enterprise_rp_list(trussed);
+    Ok(())
+}
+
+/// Whether the list consists of non-empty entries, each
+/// prefixed with its length.
+fn valid_rp_list(mut list: &[u8]) -> bool {
+    while let Some((&length, rest)) = list.split_first() {
+        if length == 0 || rest.len() < length as usize {
+            return false;
+        }
+        list = &rest[length as usize..];
+    }
+    true
+}
+
+fn read_list<T: TrussedClient>(trussed: &mut T, path: &str) -> Message {
+    try_syscall!(trussed.read_file(Location::Internal, PathBuf::from(path)))
+        .map(|reply| reply.data)
+        .unwrap_or_default()
+}
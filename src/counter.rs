@@ -0,0 +1,95 @@
+//! Counters persisted in the admin app's Trussed storage.
+//!
+//! Each counter is stored in a file of its own, holding its value as a
+//! big-endian `u64`. A counter that was never incremented reads as zero.
+
+use core::convert::TryFrom;
+use trussed::{
+    try_syscall,
+    types::{Location, Message, PathBuf},
+    Client as TrussedClient,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Counter {
+    /// Times the device entered its recovery mode, as recorded by the platform.
+    RecoveryAttempts,
+    /// Non-idempotent commands executed, see [`BatchedCounter`].
+    Transactions,
+    /// Setting writes, to detect conflicting writes.
+    ConfigGeneration,
+    /// Times the platform throttled the device for its temperature.
+    ThrottleEvents,
+    /// Firmware updates that completed.
+    SuccessfulUpdates,
+}
+
+impl Counter {
+    fn path(self) -> PathBuf {
+        PathBuf::from(match self {
+            Counter::RecoveryAttempts => "recovery-attempts",
+            Counter::Transactions => "transactions",
+            Counter::ConfigGeneration => "config-generation",
+            Counter::ThrottleEvents => "throttle-events",
+            Counter::SuccessfulUpdates => "successful-updates",
+        })
+    }
+
+    pub(crate) fn read<T: TrussedClient>(self, trussed: &mut T) -> u64 {
+        try_syscall!(trussed.read_file(Location::Internal, self.path()))
+            .ok()
+            .and_then(|reply| <[u8; 8]>::try_from(reply.data.as_slice()).ok())
+            .map(u64::from_be_bytes)
+            .unwrap_or(0)
+    }
+
+    fn write<T: TrussedClient>(self, trussed: &mut T, value: u64) {
+        let data = Message::from_slice(&value.to_be_bytes()).unwrap();
+        try_syscall!(trussed.write_file(Location::Internal, self.path(), data, None)).ok();
+    }
+
+    pub(crate) fn increment<T: TrussedClient>(self, trussed: &mut T) {
+        let value = self.read(trussed).saturating_add(1);
+        self.write(trussed, value);
+    }
+}
+
+/// A counter that is only written every [`BATCH`][Self::BATCH]
+/// increments, to limit flash wear.
+///
+/// What is stored is the value up to which increments are reserved.
+/// After a reboot, the counter continues from there, skipping the
+/// increments that were lost, so that it never repeats a value.
+pub(crate) struct BatchedCounter {
+    counter: Counter,
+    value: Option<u64>,
+    reserved: u64,
+}
+
+impl BatchedCounter {
+    const BATCH: u64 = 32;
+
+    pub(crate) const fn new(counter: Counter) -> Self {
+        Self { counter, value: None, reserved: 0 }
+    }
+
+    pub(crate) fn read<T: TrussedClient>(&mut self, trussed: &mut T) -> u64 {
+        match self.value {
+            Some(value) => value,
+            None => {
+                self.reserved = self.counter.read(trussed);
+                self.value = Some(self.reserved);
+                self.reserved
+            }
+        }
+    }
+
+    pub(crate) fn increment<T: TrussedClient>(&mut self, trussed: &mut T) {
+        let value = self.read(trussed).saturating_add(1);
+        if value > self.reserved {
+            self.reserved = value.saturating_add(Self::BATCH - 1);
+            self.counter.write(trussed, self.reserved);
+        }
+        self.value = Some(value);
+    }
+}
@@ -0,0 +1,65 @@
+//! Log of failed commands persisted in the admin app's Trussed storage.
+//!
+//! The log holds the last [`CAPACITY`] failed commands, oldest first,
+//! each as its command id followed by its error code. It survives
+//! reboots, unlike the error counters kept by the app.
+
+use trussed::{
+    try_syscall,
+    types::{Location, Message, PathBuf, Vec},
+    Client as TrussedClient,
+};
+
+use crate::command::Error;
+
+pub(crate) const CAPACITY: usize = 64;
+const BATCH: usize = 8;
+const PATH: &str = "error-log";
+
+/// The error log, only written every [`BATCH`] appended entries,
+/// to limit flash wear.
+///
+/// Entries appended since the last write are kept in memory, and lost
+/// if the device reboots other than through the admin app.
+pub(crate) struct BatchedErrorLog {
+    pending: Vec<u8, { 2 * BATCH }>,
+}
+
+impl BatchedErrorLog {
+    pub(crate) const fn new() -> Self {
+        Self { pending: Vec::new() }
+    }
+
+    /// The stored entries followed by the pending ones.
+    pub(crate) fn read<T: TrussedClient>(&self, trussed: &mut T) -> Message {
+        let log = try_syscall!(trussed.read_file(Location::Internal, PathBuf::from(PATH)))
+            .map(|reply| reply.data)
+            .unwrap_or_default();
+        let start = (log.len() + self.pending.len()).saturating_sub(2 * CAPACITY);
+        let mut data = Message::from_slice(&log[start.min(log.len())..]).unwrap();
+        data.extend_from_slice(&self.pending[start.saturating_sub(log.len())..]).ok();
+        data
+    }
+
+    pub(crate) fn append<T: TrussedClient>(&mut self, trussed: &mut T, id: u8, error: Error) {
+        self.pending.extend_from_slice(&[id, error.into()]).ok();
+        if self.pending.is_full() {
+            self.flush(trussed);
+        }
+    }
+
+    /// Writes the pending entries.
+    pub(crate) fn flush<T: TrussedClient>(&mut self, trussed: &mut T) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let data = self.read(trussed);
+        try_syscall!(trussed.write_file(Location::Internal, PathBuf::from(PATH), data, None)).ok();
+        self.pending.clear();
+    }
+
+    pub(crate) fn clear<T: TrussedClient>(&mut self, trussed: &mut T) {
+        self.pending.clear();
+        try_syscall!(trussed.remove_file(Location::Internal, PathBuf::from(PATH))).ok();
+    }
+}
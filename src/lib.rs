@@ -11,4 +11,11 @@ extern crate delog;
 generate_macros!();
 
 mod admin;
-pub use admin::{App, Reboot};
+mod command;
+mod config;
+mod counter;
+mod error_log;
+mod update;
+pub use admin::{App, Attestation, Reboot};
+pub use command::{Command, Error};
+pub use config::Setting;
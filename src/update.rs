@@ -0,0 +1,31 @@
+//! Tracking of firmware updates across the reboots they take.
+//!
+//! Before rebooting into the firmware update mode, the app records the
+//! version it runs. If it finds another version recorded after the next
+//! boot, the update completed.
+
+use core::convert::TryFrom;
+use trussed::{
+    try_syscall,
+    types::{Location, Message, PathBuf},
+    Client as TrussedClient,
+};
+
+const PATH: &str = "pending-update";
+
+pub(crate) fn begin<T: TrussedClient>(trussed: &mut T, version: u32) {
+    let data = Message::from_slice(&version.to_be_bytes()).unwrap();
+    try_syscall!(trussed.write_file(Location::Internal, PathBuf::from(PATH), data, None)).ok();
+}
+
+/// Whether an update begun before the last boot completed, forgetting about it.
+pub(crate) fn completed<T: TrussedClient>(trussed: &mut T, version: u32) -> bool {
+    let previous = try_syscall!(trussed.read_file(Location::Internal, PathBuf::from(PATH)))
+        .ok()
+        .and_then(|reply| <[u8; 4]>::try_from(reply.data.as_slice()).ok())
+        .map(u32::from_be_bytes);
+    if previous.is_some() {
+        try_syscall!(trussed.remove_file(Location::Internal, PathBuf::from(PATH))).ok();
+    }
+    previous.is_some_and(|previous| previous != version)
+}
@@ -0,0 +1,1064 @@
+mod device;
+
+use std::time::Duration;
+
+use admin_app::{Attestation, Command, Error, Setting};
+use apdu_dispatch::{app::Interface, iso7816::Status};
+use ctaphid_dispatch::app as hid;
+use device::{grant_presence, platform, presence_prompts, Device, Platform, Rebooted, SIGNATURE, TRNG_BYTE, UUID, VERSION};
+
+const EXTENDED: u8 = 0x64;
+const RNG: u8 = 0x60;
+const UPDATE: u8 = 0x51;
+const VERSION_INS: u8 = 0x61;
+
+fn u32_bytes(value: u32) -> Vec<u8> {
+    value.to_be_bytes().to_vec()
+}
+
+fn u64_bytes(value: u64) -> Vec<u8> {
+    value.to_be_bytes().to_vec()
+}
+
+fn extended(command: Command, data: &[u8]) -> Vec<u8> {
+    let mut request = vec![command.into()];
+    request.extend_from_slice(data);
+    request
+}
+
+#[test]
+fn vendor_commands() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::Version, &[]), Ok(u32_bytes(VERSION)));
+    assert_eq!(device.call(Command::Uuid, &[]), Ok(UUID.to_vec()));
+    assert_eq!(device.call(Command::Locked, &[]), Ok(vec![0]));
+    platform(|platform| platform.locked = true);
+    assert_eq!(device.call(Command::Locked, &[]), Ok(vec![1]));
+    assert_eq!(device.call(Command::Rng, &[]).unwrap().len(), 57);
+    assert_eq!(device.call(Command::Wink, &[]), Ok(vec![]));
+    assert_eq!(device.call_rebooting(Command::Reboot, &[]), Rebooted::Normal);
+}
+
+#[test]
+fn extended_framing_is_shared_by_both_transports() {
+    let mut device = Device::new();
+    let version = extended(Command::Version, &[]);
+    assert_eq!(device.apdu(Interface::Contact, VERSION_INS, 0, &[]), Ok(u32_bytes(VERSION)));
+    assert_eq!(device.apdu(Interface::Contact, EXTENDED, 0, &version), Ok(u32_bytes(VERSION)));
+    assert_eq!(device.hid(Command::Version.into(), &[]), Ok(u32_bytes(VERSION)));
+
+    assert_eq!(device.apdu(Interface::Contact, EXTENDED, 0, &[]), Err(Status::WrongLength));
+    assert_eq!(device.apdu(Interface::Contact, EXTENDED, 0, &[0x01]), Err(Status::InstructionNotSupportedOrInvalid));
+    assert_eq!(device.hid(0x01, &[]), Err(hid::Error::InvalidCommand));
+    // Extended commands are not instructions of their own
+    let checksum = Command::ProvisioningChecksum.into();
+    assert_eq!(device.apdu(Interface::Contact, checksum, 0, &[]), Err(Status::InstructionNotSupportedOrInvalid));
+}
+
+#[test]
+fn updates_require_presence_and_a_contact_interface() {
+    let mut device = Device::new();
+    assert_eq!(device.apdu(Interface::Contactless, UPDATE, 0, &[]), Err(Status::ConditionsOfUseNotSatisfied));
+    grant_presence(Some(0));
+    assert_eq!(device.call(Command::Update, &[]), Err(Error::NotAvailable));
+    assert_eq!(platform(|platform| platform.rebooted), None);
+    grant_presence(None);
+    assert_eq!(device.call_rebooting(Command::Update, &[]), Rebooted::FirmwareUpdate);
+    assert_eq!(device.call_rebooting(Command::Update, &[0x01]), Rebooted::FirmwareUpdateDestructive);
+}
+
+#[test]
+fn platform_readings() {
+    type Setup = fn(&mut Platform);
+    let readings: &[(Command, &[u8], Setup, Vec<u8>)] = &[
+        (Command::RngReseedInfo, &[], |p| p.rng_reseed_info = Some((Duration::from_secs(60), Duration::from_millis(1500))),
+            [u32_bytes(60), u64_bytes(1500)].concat()),
+        (Command::UpdateDownloadProgress, &[], |p| p.update_progress = Some(150), vec![100]),
+        (Command::EnrolledFingerprints, &[], |p| p.enrolled_fingerprints = Some(2), vec![2]),
+        (Command::SecureBootRollbackIndex, &[], |p| p.secure_boot_rollback_index = Some(9), u32_bytes(9)),
+        (Command::PowerConsumption, &[], |p| p.power_consumption_mw = Some(120), u32_bytes(120)),
+        (Command::HeapFragmentation, &[], |p| p.heap_fragmentation = Some(130), vec![100]),
+        (Command::AttestationSignCount, &[], |p| p.attestation_sign_count = Some(77), u32_bytes(77)),
+        (Command::FirmwareVerifyTime, &[], |p| p.last_verify_time_ms = Some(42), u32_bytes(42)),
+        (Command::GetTouchSensitivity, &[], |p| p.touch_sensitivity = Some(200), vec![200]),
+        (Command::LargeBlobUsage, &[], |p| p.large_blob_usage = Some(512), u32_bytes(512)),
+        (Command::CredentialIntegrity, &[1], |p| p.corrupt_credentials = Some(2), vec![0, 0, 2]),
+        (Command::RollbackFuseState, &[], |p| p.rollback_fuse_state = Some(0b101), u32_bytes(0b101)),
+        (Command::MaxFirmwareImageSize, &[], |p| p.max_image_size = Some(0x6_0000), u32_bytes(0x6_0000)),
+        (Command::CryptoThroughput, &[], |p| p.crypto_ops_per_sec = Some(800), u32_bytes(800)),
+        (Command::SeCommErrorCount, &[], |p| p.se_comm_errors = Some(3), u32_bytes(3)),
+        (Command::WearLevelingStatus, &[], |p| p.wear_leveling_status = Some(90), vec![90]),
+        (Command::InterruptLatency, &[], |p| p.max_interrupt_latency_us = Some(25), u32_bytes(25)),
+        (Command::FreeCounterSlots, &[], |p| p.free_counter_slots = Some(100), u32_bytes(100)),
+        (Command::ConcurrencyState, &[], |p| p.dispatch_lock_state = Some((2, true)), vec![2, 1]),
+        (Command::BootloaderProtocolVersion, &[], |p| p.bootloader_protocol_version = Some(3), vec![0, 3]),
+        (Command::EntropyPoolLevel, &[], |p| p.entropy_pool_percent = Some(150), vec![100]),
+        (Command::DispatchStackUsage, &[], |p| p.dispatch_stack_usage = Some(2048), u32_bytes(2048)),
+        (Command::PeakCommandMemory, &[], |p| p.peak_command_alloc = Some(1024), u32_bytes(1024)),
+        (Command::UsbDescriptorConfig, &[], |p| p.usb_descriptor_config = Some((0x1209, 0xBEEE, 0x03)),
+            vec![0x12, 0x09, 0xBE, 0xEE, 0x03]),
+        (Command::SchedulerDrift, &[], |p| p.scheduler_drift_ppm = Some(-12), (-12i32).to_be_bytes().to_vec()),
+        (Command::FirmwareChecksum, &[], |p| p.firmware_crc32 = Some(0xDEAD_BEEF), u32_bytes(0xDEAD_BEEF)),
+        (Command::DispatcherVersion, &[], |p| p.dispatcher_version = Some(0x0001_0002), u32_bytes(0x0001_0002)),
+        (Command::LastKeepaliveCount, &[], |p| p.last_keepalive_count = Some(6), u32_bytes(6)),
+        (Command::TotalBytesWritten, &[], |p| p.total_flash_bytes_written = Some(1 << 40), u64_bytes(1 << 40)),
+    ];
+    let mut device = Device::new();
+    for (command, data, setup, expected) in readings {
+        assert_eq!(device.call(*command, data), Err(Error::NotAvailable), "{:?}", command);
+        platform(setup);
+        assert_eq!(device.call(*command, data).as_ref(), Ok(expected), "{:?}", command);
+    }
+}
+
+#[test]
+fn platform_readings_without_fallible_hooks() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::TickResolution, &[]), Ok(u32_bytes(1000)));
+    assert_eq!(device.call(Command::DebugPortStatus, &[]), Ok(vec![0]));
+    platform(|platform| platform.debug_port_locked = true);
+    assert_eq!(device.call(Command::DebugPortStatus, &[]), Ok(vec![1]));
+    assert_eq!(device.call(Command::RegisteredAppIds, &[]), Ok(vec![]));
+    platform(|platform| {
+        platform.app_ids = &[1, 2, 3];
+        platform.disabled_apps = &[2];
+    });
+    assert_eq!(device.call(Command::RegisteredAppIds, &[]), Ok(vec![1, 1, 2, 0, 3, 1]));
+}
+
+#[test]
+fn settings_round_trip() {
+    let settings: &[(Command, Command, &[u8])] = &[
+        (Command::GetKeyAttestationPolicy, Command::SetKeyAttestationPolicy, &[1]),
+        (Command::GetMaxClockSkew, Command::SetMaxClockSkew, &[0, 0, 0, 60]),
+        (Command::GetUnknownCommandPolicy, Command::SetUnknownCommandPolicy, &[1]),
+        (Command::GetMaxFingerprints, Command::SetMaxFingerprints, &[3]),
+        (Command::GetBiometricFailPolicy, Command::SetBiometricFailPolicy, &[1]),
+        (Command::GetPinLockoutDuration, Command::SetPinLockoutDuration, &[0, 0, 0x0E, 0x10]),
+        (Command::GetRkEvictionPolicy, Command::SetRkEvictionPolicy, &[2]),
+        (Command::GetDispatchPriority, Command::SetDispatchPriority, &[0x1B, 0x2E, 0x3D, 0x4C]),
+        (Command::GetPresenceFailPolicy, Command::SetPresenceFailPolicy, &[1]),
+        (Command::GetLargeBlobLimit, Command::SetLargeBlobLimit, &[0, 0, 0x08, 0]),
+        (Command::GetCredBlobLimit, Command::SetCredBlobLimit, &[0, 64]),
+        (Command::GetUpdateCheckInterval, Command::SetUpdateCheckInterval, &[0, 1, 0x51, 0x80]),
+        (Command::GetPresenceQueueSize, Command::SetPresenceQueueSize, &[3]),
+        (Command::GetFieldLossPolicy, Command::SetFieldLossPolicy, &[1]),
+        (Command::GetRngFailAction, Command::SetRngFailAction, &[2]),
+        (Command::GetPinComplexity, Command::SetPinComplexity, &[0x1F]),
+        (Command::GetConveyancePolicy, Command::SetConveyancePolicy, &[3]),
+        (Command::GetMaxConcurrentAssertions, Command::SetMaxConcurrentAssertions, &[4]),
+        (Command::GetCredentialNamingPolicy, Command::SetCredentialNamingPolicy, &[2]),
+        (Command::GetPanicPolicy, Command::SetPanicPolicy, &[1]),
+        (Command::GetUserHandleLimit, Command::SetUserHandleLimit, &[32]),
+        (Command::GetMaxCertificates, Command::SetMaxCertificates, &[10]),
+                (Command::GetNfcChainingPolicy, Command::SetNfcChainingPolicy, &[1]),
+                (Command::GetDowngradePolicy, Command::SetDowngradePolicy, &[2]),
+                (Command::GetCredentialTtl, Command::SetCredentialTtl, &[0, 1, 0x51, 0x80]),
+                (Command::GetMaxNfcTransactions, Command::SetMaxNfcTransactions, &[4]),
+                (Command::GetKeyProtectionLevel, Command::SetKeyProtectionLevel, &[2]),
+                (Command::GetExpiredCertPolicy, Command::SetExpiredCertPolicy, &[1]),
+                (Command::GetChunkSize, Command::SetChunkSize, &[0, 128]),
+                (Command::GetBackupEligibility, Command::SetBackupEligibility, &[1]),
+                (Command::GetSignatureUpPolicy, Command::SetSignatureUpPolicy, &[1]),
+                (Command::GetRngMode, Command::SetRngMode, &[1]),
+                (Command::GetOriginBindingPolicy, Command::SetOriginBindingPolicy, &[1]),
+                (Command::GetSignCountPolicy, Command::SetSignCountPolicy, &[2]),
+                (Command::GetUnknownInsPolicy, Command::SetUnknownInsPolicy, &[1]),
+    ];
+    let mut device = Device::new();
+    platform(|platform| {
+        platform.fingerprint_capacity = Some(5);
+        platform.large_blob_capacity = Some(4096);
+        platform.certificate_capacity = Some(16);
+        platform.trng = true;
+    });
+    for (get, set, value) in settings {
+        assert_ne!(device.call(*get, &[]).as_deref(), Ok(*value), "{:?}", get);
+        assert_eq!(device.call(*set, value), Ok(vec![]), "{:?}", set);
+        assert_eq!(device.call(*get, &[]).as_deref(), Ok(*value), "{:?}", get);
+    }
+    let writes = settings.len() as u32;
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(u32_bytes(writes)));
+
+    device.reboot();
+    for (get, _, value) in settings {
+        assert_eq!(device.call(*get, &[]).as_deref(), Ok(*value), "{:?}", get);
+    }
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(u32_bytes(writes)));
+}
+
+#[test]
+fn setting_defaults() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::GetKeyAttestationPolicy, &[]), Ok(vec![0]));
+    assert_eq!(device.call(Command::GetMaxClockSkew, &[]), Ok(u32_bytes(300)));
+    assert_eq!(device.call(Command::GetDispatchPriority, &[]), Ok(vec![0; 4]));
+    assert_eq!(device.call(Command::GetPresenceQueueSize, &[]), Ok(vec![1]));
+    assert_eq!(device.call(Command::GetUserHandleLimit, &[]), Ok(vec![64]));
+    assert_eq!(device.call(Command::GetMaxNfcTransactions, &[]), Ok(vec![1]));
+    assert_eq!(device.call(Command::GetChunkSize, &[]), Ok(vec![0, 255]));
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(vec![0; 4]));
+}
+
+#[test]
+fn invalid_setting_values_are_refused() {
+    let mut device = Device::new();
+    let invalid: &[(Command, &[u8], Error)] = &[
+        (Command::SetKeyAttestationPolicy, &[2], Error::InvalidValue),
+        (Command::SetKeyAttestationPolicy, &[0, 1], Error::InvalidLength),
+        (Command::SetMaxClockSkew, &[60], Error::InvalidLength),
+        (Command::SetPinLockoutDuration, &[0, 1, 0x51, 0x81], Error::InvalidValue),
+        (Command::SetCredBlobLimit, &[0, 31], Error::InvalidValue),
+        (Command::SetMaxConcurrentAssertions, &[0], Error::InvalidValue),
+        (Command::SetUserHandleLimit, &[65], Error::InvalidValue),
+                (Command::SetMaxNfcTransactions, &[0], Error::InvalidValue),
+                (Command::SetKeyProtectionLevel, &[3], Error::InvalidValue),
+                (Command::SetChunkSize, &[0, 0], Error::InvalidValue),
+                (Command::SetSignCountPolicy, &[3], Error::InvalidValue),
+                (Command::SetDispatchPriority, &[0x1B, 0x2E, 0x3D], Error::InvalidLength),
+                (Command::SetPresenceQueueSize, &[0], Error::InvalidValue),
+    ];
+    for (command, data, error) in invalid {
+        assert_eq!(device.call(*command, data), Err(*error), "{:?} {:?}", command, data);
+    }
+    assert_eq!(device.call(Command::GetKeyAttestationPolicy, &[]), Ok(vec![0]));
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(vec![0; 4]));
+}
+
+#[test]
+fn settings_are_handed_to_the_runner() {
+    let mut device = Device::new();
+    assert_eq!(device.app().setting(Setting::KeyAttestationPolicy), 0);
+    device.call(Command::SetKeyAttestationPolicy, &[1]).unwrap();
+    assert_eq!(device.app().setting(Setting::KeyAttestationPolicy), 1);
+    device.reboot();
+    assert_eq!(device.app().setting(Setting::KeyAttestationPolicy), 1);
+}
+
+#[test]
+fn setting_writes_require_presence() {
+    let mut device = Device::new();
+    grant_presence(Some(0));
+    assert_eq!(device.call(Command::SetKeyAttestationPolicy, &[1]), Err(Error::NotAvailable));
+    assert_eq!(device.call(Command::GetKeyAttestationPolicy, &[]), Ok(vec![0]));
+    grant_presence(Some(1));
+    assert_eq!(device.call(Command::SetKeyAttestationPolicy, &[1]), Ok(vec![]));
+    assert_eq!(device.call(Command::GetKeyAttestationPolicy, &[]), Ok(vec![1]));
+}
+
+#[test]
+fn recovery_attempts_are_recorded_by_the_platform() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::RecoveryAttemptCount, &[]), Ok(vec![0; 4]));
+    device.app().record_recovery_attempt();
+    assert_eq!(device.call(Command::RecoveryAttemptCount, &[]), Ok(u32_bytes(1)));
+    assert_eq!(device.call_rebooting(Command::Update, &[0x01]), Rebooted::FirmwareUpdateDestructive);
+    device.reboot();
+    assert_eq!(device.call(Command::RecoveryAttemptCount, &[]), Ok(u32_bytes(1)));
+}
+
+#[test]
+fn provisioning_checksum_covers_the_provisioned_state() {
+    let mut device = Device::new();
+    let blank = device.call(Command::ProvisioningChecksum, &[]).unwrap();
+    assert_eq!(blank.len(), 32);
+    assert_eq!(device.call(Command::ProvisioningChecksum, &[]), Ok(blank.clone()));
+
+    device.call(Command::SetMaxClockSkew, &[0, 0, 0, 60]).unwrap();
+    let configured = device.call(Command::ProvisioningChecksum, &[]).unwrap();
+    assert_ne!(configured, blank);
+
+    device.call(Command::SetEnterpriseRpList, &[1, 0, 0, 5, b'a', b'.', b'c', b'o', b'm']).unwrap();
+    let listed = device.call(Command::ProvisioningChecksum, &[]).unwrap();
+    assert_ne!(listed, configured);
+
+    platform(|platform| platform.attestation_key = true);
+    let provisioned = device.call(Command::ProvisioningChecksum, &[]).unwrap();
+    assert_ne!(provisioned, listed);
+
+    platform(|platform| platform.attestation_certificate = Some(b"certificate"));
+    assert_ne!(device.call(Command::ProvisioningChecksum, &[]).unwrap(), provisioned);
+}
+
+#[test]
+fn unknown_hid_commands_follow_the_policy() {
+    let mut device = Device::new();
+    platform(|platform| platform.rail_voltages_mv = &[3300]);
+    assert_eq!(device.hid(0x01, &[]), Err(hid::Error::InvalidCommand));
+    device.call(Command::SetUnknownCommandPolicy, &[1]).unwrap();
+    assert_eq!(device.hid(0x01, &[]), Ok(vec![]));
+    // Known commands keep their errors
+    assert_eq!(device.hid(Command::RailVoltages.into(), &[5]), Err(hid::Error::InvalidCommand));
+    assert_eq!(device.hid(Command::RngReseedInfo.into(), &[]), Err(hid::Error::InvalidLength));
+}
+
+#[test]
+fn silently_answered_probes_are_no_errors() {
+    let mut device = Device::new();
+    device.call(Command::SetUnknownCommandPolicy, &[1]).unwrap();
+    assert_eq!(device.hid(0x01, &[]), Ok(vec![]));
+    assert_eq!(device.hid(Command::LastError.into(), &[]), Ok(vec![0, 0]));
+    assert_eq!(device.hid(Command::ErrorRate.into(), &[]), Ok(vec![0, 0]));
+    assert_eq!(device.call(Command::PersistentErrorLog, &[]), Ok(vec![]));
+    // The policy only covers CTAPHID
+    assert_eq!(device.apdu(Interface::Contact, EXTENDED, 0, &[0x01]), Err(Status::InstructionNotSupportedOrInvalid));
+    assert_eq!(device.call(Command::PersistentErrorLog, &[]), Ok(vec![0x01, 4]));
+}
+
+#[test]
+fn platform_bounded_settings() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::SetMaxFingerprints, &[1]), Err(Error::NotAvailable));
+    assert_eq!(device.call(Command::SetLargeBlobLimit, &[0, 0, 0x08, 0]), Err(Error::NotAvailable));
+    assert_eq!(device.call(Command::SetMaxCertificates, &[1]), Err(Error::NotAvailable));
+    platform(|platform| {
+        platform.fingerprint_capacity = Some(5);
+        platform.large_blob_capacity = Some(4096);
+        platform.certificate_capacity = Some(16);
+    });
+    assert_eq!(device.call(Command::SetMaxFingerprints, &[6]), Err(Error::InvalidValue));
+    assert_eq!(device.call(Command::SetMaxFingerprints, &[5]), Ok(vec![]));
+    assert_eq!(device.call(Command::SetLargeBlobLimit, &[0, 0, 0x20, 0]), Err(Error::InvalidValue));
+    assert_eq!(device.call(Command::SetLargeBlobLimit, &[0, 0, 0x01, 0]), Err(Error::InvalidValue));
+    assert_eq!(device.call(Command::SetLargeBlobLimit, &[0, 0, 0x10, 0]), Ok(vec![]));
+    assert_eq!(device.call(Command::SetMaxCertificates, &[17]), Err(Error::InvalidValue));
+    assert_eq!(device.call(Command::SetMaxCertificates, &[16]), Ok(vec![]));
+}
+
+#[test]
+fn transaction_counter_counts_non_idempotent_commands() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::TransactionCounter, &[]), Ok(u64_bytes(0)));
+    device.call(Command::Version, &[]).unwrap();
+    device.call(Command::GetMaxClockSkew, &[]).unwrap();
+    assert_eq!(device.call(Command::TransactionCounter, &[]), Ok(u64_bytes(0)));
+    device.call(Command::SetMaxClockSkew, &[0, 0, 0, 60]).unwrap();
+    device.call(Command::Rng, &[]).unwrap();
+    assert_eq!(device.call(Command::TransactionCounter, &[]), Ok(u64_bytes(2)));
+
+    // Continues after the reserved batch, never repeating a value
+    device.reboot();
+    assert_eq!(device.call(Command::TransactionCounter, &[]), Ok(u64_bytes(32)));
+    device.call(Command::Rng, &[]).unwrap();
+    assert_eq!(device.call(Command::TransactionCounter, &[]), Ok(u64_bytes(33)));
+}
+
+/// Runs every command with presence denied, checking that exactly those
+/// reported as requiring presence prompt for it.
+fn assert_presence_requirements_match(device: &mut Device) {
+    grant_presence(None);
+    let bitmap = device.call(Command::PresenceRequirements, &[]).unwrap();
+    assert_eq!(bitmap.len(), 32);
+    grant_presence(Some(0));
+    for id in 0..=u8::MAX {
+        let Ok(command) = Command::try_from(id) else { continue };
+        let requires_presence = bitmap[id as usize / 8] & (1 << (id % 8)) != 0;
+        let prompts = presence_prompts();
+        // Chunked writes prompt for their last chunk
+        let data: &[u8] = match command {
+            Command::SetEnterpriseRpList => &[1, 0, 0],
+            _ => &[],
+        };
+        if command == Command::Reboot {
+            assert_eq!(device.call_rebooting(command, data), Rebooted::Normal);
+        } else if requires_presence {
+            assert_eq!(device.call(command, data), Err(Error::NotAvailable), "{:?}", command);
+        } else {
+            device.call(command, data).ok();
+        }
+        assert_eq!(presence_prompts() > prompts, requires_presence, "{:?}", command);
+    }
+}
+
+#[test]
+fn presence_requirements_match_the_presence_checks() {
+    let mut device = Device::new();
+    assert_presence_requirements_match(&mut device);
+    device.require_presence_for_diagnostics();
+    assert_presence_requirements_match(&mut device);
+}
+
+#[test]
+fn write_conflicts_are_rejected_under_the_policy() {
+    let mut device = Device::new();
+    device.call(Command::SetWriteConflictPolicy, &[1]).unwrap();
+    let generation = device.call(Command::ConfigGeneration, &[]).unwrap();
+    assert_eq!(generation, u32_bytes(1));
+
+    let stale = [u32_bytes(0), vec![0, 0, 0, 60]].concat();
+    assert_eq!(device.call(Command::SetMaxClockSkew, &stale), Err(Error::NotAvailable));
+    assert_eq!(device.call(Command::SetKeyAttestationPolicy, &[1]), Err(Error::InvalidLength));
+    let current = [generation, vec![0, 0, 0, 60]].concat();
+    assert_eq!(device.call(Command::SetMaxClockSkew, &current), Ok(vec![]));
+    assert_eq!(device.call(Command::GetMaxClockSkew, &[]), Ok(u32_bytes(60)));
+    assert_eq!(device.call(Command::SetMaxClockSkew, &current), Err(Error::NotAvailable));
+}
+
+#[test]
+fn the_last_write_wins_by_default() {
+    let mut device = Device::new();
+    // A host reads the configuration, then another host changes it
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(vec![0; 4]));
+    device.call(Command::SetMaxClockSkew, &[0, 0, 0, 60]).unwrap();
+    // The first host's write, based on the stale configuration, overwrites it
+    assert_eq!(device.call(Command::SetMaxClockSkew, &[0, 0, 0, 90]), Ok(vec![]));
+    assert_eq!(device.call(Command::GetMaxClockSkew, &[]), Ok(u32_bytes(90)));
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(u32_bytes(2)));
+}
+
+#[test]
+fn wink_shows_diagnostics_on_a_display() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::Wink, &[]), Ok(vec![]));
+    assert!(platform(|platform| platform.winked && !platform.diagnostics_requested));
+
+    // Without a display, the device winks instead
+    platform(|platform| platform.winked = false);
+    assert_eq!(device.call(Command::Wink, &[0x01]), Ok(vec![]));
+    assert!(platform(|platform| platform.winked && platform.diagnostics_requested));
+
+    platform(|platform| {
+        platform.display = true;
+        platform.winked = false;
+        platform.diagnostics_requested = false;
+    });
+    assert_eq!(device.call(Command::Wink, &[0x01]), Ok(vec![]));
+    assert!(platform(|platform| platform.diagnostics_requested && !platform.winked));
+}
+
+#[test]
+fn touch_sensitivity_is_set_on_the_platform() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::SetTouchSensitivity, &[10]), Err(Error::NotAvailable));
+    platform(|platform| platform.touch_sensitivity = Some(100));
+    assert_eq!(device.call(Command::SetTouchSensitivity, &[]), Err(Error::InvalidLength));
+    assert_eq!(device.call(Command::SetTouchSensitivity, &[10]), Ok(vec![]));
+    assert_eq!(device.call(Command::GetTouchSensitivity, &[]), Ok(vec![10]));
+}
+
+#[test]
+fn response_buffer_high_water_tracks_apdu_responses() {
+    let mut device = Device::new();
+    let high_water = u8::from(Command::ResponseBufferHighWater);
+    device.hid(Command::Rng.into(), &[]).unwrap();
+    assert_eq!(device.hid(high_water, &[]), Ok(vec![0; 4]));
+    assert_eq!(device.apdu(Interface::Contact, RNG, 0, &[]).unwrap().len(), 57);
+    device.apdu(Interface::Contact, VERSION_INS, 0, &[]).unwrap();
+    assert_eq!(device.hid(high_water, &[]), Ok(u32_bytes(57)));
+
+    // Extended responses, longer than a short APDU's 256 bytes
+    let list = [&[29][..], &[b'a'; 29]].concat().repeat(10);
+    device.hid(Command::SetEnterpriseRpList.into(), &[&[1, 0, 0], &list[..]].concat()).unwrap();
+    let read_list = extended(Command::GetEnterpriseRpList, &[]);
+    assert_eq!(device.apdu(Interface::Contact, EXTENDED, 0, &read_list), Ok(list));
+    assert_eq!(device.hid(high_water, &[]), Ok(u32_bytes(300)));
+    device.reboot();
+    assert_eq!(device.hid(high_water, &[]), Ok(vec![0; 4]));
+}
+
+#[test]
+fn failed_presence_hardware_follows_the_policy() {
+    let mut device = Device::new();
+    platform(|platform| {
+        platform.presence_hardware_failed = true;
+        platform.alternate_presence = true;
+    });
+    assert_eq!(device.call(Command::SetMaxClockSkew, &[0, 0, 0, 60]), Err(Error::NotAvailable));
+
+    platform(|platform| platform.presence_hardware_failed = false);
+    device.call(Command::SetPresenceFailPolicy, &[1]).unwrap();
+    platform(|platform| platform.presence_hardware_failed = true);
+    assert_eq!(device.call(Command::SetMaxClockSkew, &[0, 0, 0, 60]), Ok(vec![]));
+    platform(|platform| platform.alternate_presence = false);
+    assert_eq!(device.call(Command::SetMaxClockSkew, &[0, 0, 0, 90]), Err(Error::NotAvailable));
+}
+
+#[test]
+fn error_rate_is_per_thousand_commands() {
+    let mut device = Device::new();
+    let error_rate = u8::from(Command::ErrorRate);
+    assert_eq!(device.hid(error_rate, &[]), Ok(vec![0, 0]));
+    assert!(device.hid(Command::UpdateDownloadProgress.into(), &[]).is_err());
+    assert!(device.hid(Command::UpdateDownloadProgress.into(), &[]).is_err());
+    // Two of the three commands before this one failed
+    assert_eq!(device.hid(error_rate, &[]), Ok(666u16.to_be_bytes().to_vec()));
+}
+
+#[test]
+fn responses_are_signed_under_the_policy() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::SetResponseSigningPolicy, &[1]), Err(Error::NotAvailable));
+    assert_eq!(device.call(Command::GetResponseSigningPolicy, &[]), Ok(vec![0]));
+    // Refused without asking the platform to sign anything
+    assert_eq!(platform(|platform| platform.signed_message.take()), None);
+
+    platform(|platform| platform.signing = true);
+    assert_eq!(device.call(Command::SetResponseSigningPolicy, &[1]), Ok(SIGNATURE.to_vec()));
+    let version = [u32_bytes(VERSION), SIGNATURE.to_vec()].concat();
+    assert_eq!(device.call(Command::Version, &[]), Ok(version));
+    // Signed within a context, along with the command
+    let context = b"solokeys admin-app response\0";
+    let signed = [&context[..], &[Command::Version.into()], &u32_bytes(VERSION)].concat();
+    assert_eq!(platform(|platform| platform.signed_message.take()), Some(signed));
+
+    platform(|platform| platform.signing = false);
+    assert_eq!(device.hid(Command::Version.into(), &[]), Err(hid::Error::InvalidLength));
+}
+
+#[test]
+fn rng_health_failures_follow_the_fail_action() {
+    // Fail action, whether a reseed repairs the RNG, whether random
+    // bytes are returned, and whether the RNG was reseeded
+    let actions = [
+        (0, true, false, false),
+        (1, true, true, false),
+        (2, true, true, true),
+        (2, false, false, true),
+    ];
+    for (action, repairs, returned, reseeded) in actions {
+        let mut device = Device::new();
+        device.call(Command::SetRngFailAction, &[action]).unwrap();
+        assert_eq!(device.call(Command::Rng, &[]).map(|bytes| bytes.len()), Ok(57));
+        platform(|platform| {
+            platform.rng_unhealthy = true;
+            platform.reseed_repairs_rng = repairs;
+        });
+        let result = device.call(Command::Rng, &[]);
+        assert_eq!(result.is_ok(), returned, "{} {}", action, repairs);
+        if !returned {
+            assert_eq!(result, Err(Error::NotAvailable));
+        }
+        assert_eq!(platform(|platform| platform.rng_reseeds) > 0, reseeded, "{} {}", action, repairs);
+    }
+}
+
+#[test]
+fn panics_follow_the_panic_policy() {
+    let mut device = Device::new();
+    assert_eq!(device.panic(), Rebooted::Normal);
+    device.reboot();
+    device.call(Command::SetPanicPolicy, &[1]).unwrap();
+    assert_eq!(device.panic(), Rebooted::Recovery);
+
+    // Failed commands are logged before rebooting
+    fail(&mut device);
+    device.panic();
+    device.reboot();
+    assert_eq!(device.call(Command::PersistentErrorLog, &[]), Ok(vec![Command::UpdateDownloadProgress.into(), 3]));
+}
+
+#[test]
+fn credential_integrity_reports_corrupt_credentials() {
+    let mut device = Device::new();
+    platform(|platform| platform.corrupt_credentials = Some(0));
+    assert_eq!(device.call(Command::CredentialIntegrity, &[]), Err(Error::InvalidLength));
+    assert_eq!(device.call(Command::CredentialIntegrity, &[1]), Ok(vec![1, 0, 0]));
+}
+
+#[test]
+fn presence_prompts_beyond_the_queue_size_are_refused() {
+    let mut device = Device::new();
+    platform(|platform| platform.queued_presence_prompts = 1);
+    assert_eq!(device.call(Command::SetMaxClockSkew, &[0, 0, 0, 60]), Err(Error::NotAvailable));
+
+    platform(|platform| platform.queued_presence_prompts = 0);
+    device.call(Command::SetPresenceQueueSize, &[2]).unwrap();
+    platform(|platform| platform.queued_presence_prompts = 1);
+    assert_eq!(device.call(Command::SetMaxClockSkew, &[0, 0, 0, 60]), Ok(vec![]));
+    platform(|platform| platform.queued_presence_prompts = 2);
+    assert_eq!(device.call(Command::SetMaxClockSkew, &[0, 0, 0, 90]), Err(Error::NotAvailable));
+}
+
+#[test]
+fn nfc_policies_are_not_set_over_nfc() {
+    let mut device = Device::new();
+    let policies: &[(Command, &[u8])] = &[
+        (Command::SetFieldLossPolicy, &[1]),
+        (Command::SetNfcChainingPolicy, &[1]),
+        (Command::SetMaxNfcTransactions, &[4]),
+    ];
+    for (command, value) in policies {
+        let request = extended(*command, value);
+        let contactless = device.apdu(Interface::Contactless, EXTENDED, 0, &request);
+        assert_eq!(contactless, Err(Status::ConditionsOfUseNotSatisfied), "{:?}", command);
+        assert_eq!(device.apdu(Interface::Contact, EXTENDED, 0, &request), Ok(vec![]), "{:?}", command);
+    }
+}
+
+#[test]
+fn long_nfc_responses_are_refused_under_the_short_response_policy() {
+    let mut device = Device::new();
+    let mut list = Vec::new();
+    for rp in 0..10u8 {
+        list.push(29);
+        list.extend_from_slice(&[b'a' + rp; 29]);
+    }
+    device.call(Command::SetEnterpriseRpList, &[&[1, 0, 0], &list[..]].concat()).unwrap();
+    let read_list = extended(Command::GetEnterpriseRpList, &[]);
+    assert_eq!(device.apdu(Interface::Contactless, EXTENDED, 0, &read_list), Ok(list.clone()));
+
+    device.call(Command::SetNfcChainingPolicy, &[1]).unwrap();
+    assert_eq!(device.apdu(Interface::Contact, EXTENDED, 0, &read_list), Ok(list));
+    assert_eq!(device.apdu(Interface::Contactless, EXTENDED, 0, &read_list), Err(Status::WrongLength));
+    // Counted like any other failed command
+    assert_eq!(device.call(Command::LastError, &[]), Ok(vec![Command::GetEnterpriseRpList.into(), 1]));
+    let log = device.call(Command::PersistentErrorLog, &[]).unwrap();
+    assert_eq!(log, vec![Command::GetEnterpriseRpList.into(), 1]);
+}
+
+#[test]
+fn cache_statistics_count_host_reads_only() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::CacheStats, &[]), Ok(vec![0; 8]));
+    device.call(Command::GetMaxClockSkew, &[]).unwrap();
+    device.call(Command::GetMaxClockSkew, &[]).unwrap();
+    assert_eq!(device.call(Command::CacheStats, &[]), Ok([u32_bytes(1), u32_bytes(1)].concat()));
+    // Reads by the app and the runner are not counted
+    device.call(Command::ProvisioningChecksum, &[]).unwrap();
+    device.app().setting(Setting::PinComplexity);
+    assert_eq!(device.call(Command::CacheStats, &[]), Ok([u32_bytes(1), u32_bytes(1)].concat()));
+    device.call(Command::GetPinComplexity, &[]).unwrap();
+    assert_eq!(device.call(Command::CacheStats, &[]), Ok([u32_bytes(2), u32_bytes(1)].concat()));
+}
+
+#[test]
+fn provisioning_progress_follows_the_steps() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::ProvisioningProgress, &[]), Ok(vec![0]));
+    platform(|platform| platform.attestation_key = true);
+    assert_eq!(device.call(Command::ProvisioningProgress, &[]), Ok(vec![1]));
+    platform(|platform| platform.attestation_certificate = Some(b"certificate"));
+    assert_eq!(device.call(Command::ProvisioningProgress, &[]), Ok(vec![2]));
+    platform(|platform| platform.locked = true);
+    assert_eq!(device.call(Command::ProvisioningProgress, &[]), Ok(vec![3]));
+}
+
+#[test]
+fn provisioning_progress_counts_steps_in_order() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::ProvisioningProgress, &[]), Ok(vec![0]));
+    platform(|platform| platform.locked = true);
+    assert_eq!(device.call(Command::ProvisioningProgress, &[]), Ok(vec![0]));
+    platform(|platform| platform.attestation_certificate = Some(b"certificate"));
+    assert_eq!(device.call(Command::ProvisioningProgress, &[]), Ok(vec![0]));
+    platform(|platform| {
+        platform.attestation_key = true;
+        platform.attestation_certificate = None;
+    });
+    assert_eq!(device.call(Command::ProvisioningProgress, &[]), Ok(vec![1]));
+    platform(|platform| {
+        platform.attestation_certificate = Some(b"certificate");
+        platform.locked = false;
+    });
+    assert_eq!(device.call(Command::ProvisioningProgress, &[]), Ok(vec![2]));
+    platform(|platform| platform.locked = true);
+    assert_eq!(device.call(Command::ProvisioningProgress, &[]), Ok(vec![3]));
+}
+
+#[test]
+fn enterprise_rp_list_is_committed_once_complete() {
+    let mut device = Device::new();
+    let list = b"\x05a.com\x05b.org";
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &[&[0, 0, 0], &list[..7]].concat()), Ok(vec![]));
+    assert_eq!(device.call(Command::GetEnterpriseRpList, &[]), Ok(vec![]));
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(vec![0; 4]));
+    // Chunks continue where the staged list ends
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &[&[1, 0, 3], &list[7..]].concat()), Err(Error::InvalidValue));
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &[&[1, 0, 7], &list[7..]].concat()), Ok(vec![]));
+    assert_eq!(device.call(Command::GetEnterpriseRpList, &[]), Ok(list.to_vec()));
+    assert_eq!(device.app().enterprise_rp_list().as_slice(), list);
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(u32_bytes(1)));
+
+    device.reboot();
+    assert_eq!(device.call(Command::GetEnterpriseRpList, &[]), Ok(list.to_vec()));
+}
+
+#[test]
+fn malformed_enterprise_rp_lists_are_refused() {
+    let mut device = Device::new();
+    let list = b"\x05a.com";
+    device.call(Command::SetEnterpriseRpList, &[&[1, 0, 0], &list[..]].concat()).unwrap();
+    let malformed: &[&[u8]] = &[
+        &[1, 0, 0, 5, b'a'],
+        &[1, 0, 0, 0],
+        &[1, 0, 0, 1, b'a', 2],
+    ];
+    for data in malformed {
+        assert_eq!(device.call(Command::SetEnterpriseRpList, data), Err(Error::InvalidValue), "{:?}", data);
+    }
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &[1, 0]), Err(Error::InvalidLength));
+    assert_eq!(device.call(Command::GetEnterpriseRpList, &[]), Ok(list.to_vec()));
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(u32_bytes(1)));
+
+    // An empty list clears it
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &[1, 0, 0]), Ok(vec![]));
+    assert_eq!(device.call(Command::GetEnterpriseRpList, &[]), Ok(vec![]));
+}
+
+#[test]
+fn enterprise_rp_list_writes_follow_the_write_conflict_policy() {
+    let mut device = Device::new();
+    device.call(Command::SetWriteConflictPolicy, &[1]).unwrap();
+    let list = [1, 0, 0, 5, b'a', b'.', b'c', b'o', b'm'];
+    let stale = [&u32_bytes(0)[..], &list].concat();
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &stale), Err(Error::NotAvailable));
+    let current = [&u32_bytes(1)[..], &list].concat();
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &current), Ok(vec![]));
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(u32_bytes(2)));
+}
+
+#[test]
+fn only_the_last_enterprise_rp_list_chunk_requires_presence() {
+    let mut device = Device::new();
+    let list = b"\x05a.com\x05b.org";
+    grant_presence(Some(0));
+    let prompts = presence_prompts();
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &[&[0, 0, 0], &list[..7]].concat()), Ok(vec![]));
+    assert_eq!(presence_prompts(), prompts);
+    let last = [&[1, 0, 7], &list[7..]].concat();
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &last), Err(Error::NotAvailable));
+    assert!(presence_prompts() > prompts);
+    assert_eq!(device.call(Command::GetEnterpriseRpList, &[]), Ok(vec![]));
+    // The staged chunks are kept for another try
+    grant_presence(None);
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &last), Ok(vec![]));
+    assert_eq!(device.call(Command::GetEnterpriseRpList, &[]), Ok(list.to_vec()));
+}
+
+#[test]
+fn field_loss_mid_write_follows_the_policy() {
+    let list = b"\x05a.com\x05b.org";
+    let first = extended(Command::SetEnterpriseRpList, &[&[0, 0, 0], &list[..7]].concat());
+    let rest = extended(Command::SetEnterpriseRpList, &[&[1, 0, 7], &list[7..]].concat());
+    // Rolled back, or persisted for the host to resume
+    for (policy, resumable) in [(0, false), (1, true)] {
+        let mut device = Device::new();
+        device.call(Command::SetFieldLossPolicy, &[policy]).unwrap();
+        assert_eq!(device.apdu(Interface::Contactless, EXTENDED, 0, &first), Ok(vec![]));
+        device.app().field_lost();
+        let resumed = device.apdu(Interface::Contactless, EXTENDED, 0, &rest);
+        if resumable {
+            assert_eq!(resumed, Ok(vec![]));
+            assert_eq!(device.call(Command::GetEnterpriseRpList, &[]), Ok(list.to_vec()));
+        } else {
+            assert_eq!(resumed, Err(Status::IncorrectDataParameter));
+            assert_eq!(device.call(Command::GetEnterpriseRpList, &[]), Ok(vec![]));
+        }
+    }
+}
+
+#[test]
+fn field_loss_leaves_contact_writes_alone() {
+    let mut device = Device::new();
+    let list = b"\x05a.com\x05b.org";
+    device.call(Command::SetEnterpriseRpList, &[&[0, 0, 0], &list[..7]].concat()).unwrap();
+    device.app().field_lost();
+    assert_eq!(device.call(Command::SetEnterpriseRpList, &[&[1, 0, 7], &list[7..]].concat()), Ok(vec![]));
+    assert_eq!(device.call(Command::GetEnterpriseRpList, &[]), Ok(list.to_vec()));
+}
+
+#[test]
+fn throttle_events_are_recorded_by_the_platform() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::ThrottleEventCount, &[]), Ok(vec![0; 4]));
+    device.app().record_throttle_event();
+    device.app().record_throttle_event();
+    assert_eq!(device.call(Command::ThrottleEventCount, &[]), Ok(u32_bytes(2)));
+    device.reboot();
+    assert_eq!(device.call(Command::ThrottleEventCount, &[]), Ok(u32_bytes(2)));
+}
+
+#[test]
+fn uptime_since_crash_falls_back_to_uptime() {
+    let mut device = Device::new();
+    let uptime = device.call(Command::UptimeSinceLastCrash, &[]).unwrap();
+    let uptime = u64::from_be_bytes(uptime.try_into().unwrap());
+    assert!(uptime >= 1000);
+    assert_eq!(uptime % 1000, 0);
+    platform(|platform| platform.uptime_since_crash_ms = Some(1234));
+    assert_eq!(device.call(Command::UptimeSinceLastCrash, &[]), Ok(u64_bytes(1234)));
+}
+
+#[test]
+fn total_signatures_sum_the_app_counts() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::TotalSignatures, &[]), Err(Error::NotAvailable));
+    platform(|platform| {
+        platform.app_ids = &[1, 2, 3];
+        platform.signature_counts = &[(1, 40), (3, 2)];
+    });
+    assert_eq!(device.call(Command::TotalSignatures, &[]), Ok(u64_bytes(42)));
+    // Exact across reboots
+    device.reboot();
+    assert_eq!(device.call(Command::TotalSignatures, &[]), Ok(u64_bytes(42)));
+}
+
+#[test]
+fn self_test_schedule() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::SelfTestSchedule, &[]), Ok([u32_bytes(0), u64_bytes(u64::MAX)].concat()));
+    device.call(Command::SetSelfTestInterval, &u32_bytes(3600)).unwrap();
+    platform(|platform| platform.last_self_test = Some(Duration::from_secs(90)));
+    assert_eq!(device.call(Command::SelfTestSchedule, &[]), Ok([u32_bytes(3600), u64_bytes(90_000)].concat()));
+}
+
+#[test]
+fn last_error_is_cleared_by_the_next_success() {
+    let mut device = Device::new();
+    let last_error = u8::from(Command::LastError);
+    assert_eq!(device.hid(last_error, &[]), Ok(vec![0, 0]));
+    assert!(device.hid(Command::SetMaxClockSkew.into(), &[60]).is_err());
+    assert_eq!(device.hid(last_error, &[]), Ok(vec![Command::SetMaxClockSkew.into(), 1]));
+    assert_eq!(device.hid(last_error, &[]), Ok(vec![0, 0]));
+}
+
+#[test]
+fn chunk_size_is_bounded_by_the_transport() {
+    let mut device = Device::new();
+    let most = hid::Message::new().capacity() as u16;
+    assert_eq!(device.call(Command::SetChunkSize, &(most + 1).to_be_bytes()), Err(Error::InvalidValue));
+    assert_eq!(device.call(Command::SetChunkSize, &most.to_be_bytes()), Ok(vec![]));
+
+    // Over NFC, only short APDUs fit under the short-only chaining policy
+    let set = |size: u16| extended(Command::SetChunkSize, &size.to_be_bytes());
+    assert_eq!(device.apdu(Interface::Contactless, EXTENDED, 0, &set(most)), Ok(vec![]));
+    device.call(Command::SetNfcChainingPolicy, &[1]).unwrap();
+    assert_eq!(device.apdu(Interface::Contactless, EXTENDED, 0, &set(257)), Err(Status::IncorrectDataParameter));
+    assert_eq!(device.apdu(Interface::Contactless, EXTENDED, 0, &set(256)), Ok(vec![]));
+    assert_eq!(device.call(Command::SetChunkSize, &most.to_be_bytes()), Ok(vec![]));
+}
+
+/// Fails a command, logging it as `[id, 3]`.
+fn fail(device: &mut Device) {
+    assert!(device.hid(Command::UpdateDownloadProgress.into(), &[]).is_err());
+}
+
+fn error_log_entries(device: &mut Device) -> usize {
+    device.call(Command::PersistentErrorLog, &[]).unwrap().len() / 2
+}
+
+#[test]
+fn error_log_chunks_hold_at_least_one_entry() {
+    let mut device = Device::new();
+    fail(&mut device);
+    fail(&mut device);
+    let entry = vec![Command::UpdateDownloadProgress.into(), 3];
+    device.call(Command::SetChunkSize, &[0, 1]).unwrap();
+    assert_eq!(device.call(Command::PersistentErrorLog, &[]), Ok(entry.clone()));
+    assert_eq!(device.call(Command::PersistentErrorLog, &[1]), Ok(entry));
+    assert_eq!(device.call(Command::PersistentErrorLog, &[2]), Ok(vec![]));
+}
+
+#[test]
+fn error_log_survives_reboots() {
+    let mut device = Device::new();
+    for _ in 0..3 {
+        fail(&mut device);
+    }
+    let id = Command::UpdateDownloadProgress.into();
+    assert_eq!(device.call(Command::PersistentErrorLog, &[]), Ok([id, 3].repeat(3)));
+    assert_eq!(device.call_rebooting(Command::Reboot, &[]), Rebooted::Normal);
+    device.reboot();
+    assert_eq!(device.call(Command::PersistentErrorLog, &[]), Ok([id, 3].repeat(3)));
+}
+
+#[test]
+fn error_log_is_written_in_batches() {
+    let mut device = Device::new();
+    for _ in 0..9 {
+        fail(&mut device);
+    }
+    assert_eq!(error_log_entries(&mut device), 9);
+    // A crash loses the entries since the last batch
+    device.reboot();
+    assert_eq!(error_log_entries(&mut device), 8);
+}
+
+#[test]
+fn error_log_keeps_the_latest_entries() {
+    let mut device = Device::new();
+    assert!(device.hid(Command::SetMaxClockSkew.into(), &[60]).is_err());
+    for _ in 0..64 {
+        fail(&mut device);
+    }
+    let log = device.call(Command::PersistentErrorLog, &[]).unwrap();
+    assert_eq!(log, [Command::UpdateDownloadProgress.into(), 3].repeat(64));
+}
+
+#[test]
+fn error_log_is_read_in_chunks() {
+    let mut device = Device::new();
+    assert!(device.hid(Command::SetMaxClockSkew.into(), &[60]).is_err());
+    fail(&mut device);
+    fail(&mut device);
+    device.call(Command::SetChunkSize, &[0, 4]).unwrap();
+    let skew = Command::SetMaxClockSkew.into();
+    let progress = Command::UpdateDownloadProgress.into();
+    assert_eq!(device.call(Command::PersistentErrorLog, &[]), Ok(vec![skew, 1, progress, 3]));
+    assert_eq!(device.call(Command::PersistentErrorLog, &[2]), Ok(vec![progress, 3]));
+    assert_eq!(device.call(Command::PersistentErrorLog, &[0, 0]), Err(Error::InvalidLength));
+}
+
+#[test]
+fn error_log_is_cleared_with_presence() {
+    let mut device = Device::new();
+    for _ in 0..3 {
+        fail(&mut device);
+    }
+    grant_presence(Some(0));
+    assert_eq!(device.call(Command::ClearPersistentErrorLog, &[]), Err(Error::NotAvailable));
+    grant_presence(None);
+    assert_eq!(device.call(Command::ClearPersistentErrorLog, &[]), Ok(vec![]));
+    assert_eq!(device.call(Command::PersistentErrorLog, &[]), Ok(vec![]));
+    device.reboot();
+    assert_eq!(device.call(Command::PersistentErrorLog, &[]), Ok(vec![]));
+}
+
+#[test]
+fn downgrade_policy_writes_are_confirmed() {
+    let mut device = Device::new();
+    grant_presence(Some(1));
+    assert_eq!(device.call(Command::SetDowngradePolicy, &[2]), Err(Error::NotAvailable));
+    assert_eq!(device.call(Command::GetDowngradePolicy, &[]), Ok(vec![0]));
+    grant_presence(Some(2));
+    assert_eq!(device.call(Command::SetDowngradePolicy, &[2]), Ok(vec![]));
+    assert_eq!(device.call(Command::GetDowngradePolicy, &[]), Ok(vec![2]));
+}
+
+#[test]
+fn disallowed_downgrades_are_refused() {
+    let mut device = Device::new();
+    platform(|platform| platform.update_candidate_version = Some(VERSION - 1));
+    assert_eq!(device.call(Command::Update, &[]), Err(Error::NotAvailable));
+    assert_eq!(platform(|platform| platform.rebooted), None);
+    grant_presence(Some(2));
+    assert_eq!(device.call(Command::SetDowngradePolicy, &[2]), Ok(vec![]));
+    grant_presence(None);
+    platform(|platform| platform.update_candidate_version = Some(VERSION - 3));
+    assert_eq!(device.call(Command::Update, &[]), Err(Error::NotAvailable));
+    platform(|platform| platform.update_candidate_version = Some(VERSION - 2));
+    assert_eq!(device.call_rebooting(Command::Update, &[]), Rebooted::FirmwareUpdate);
+    device.boot(VERSION);
+    // Upgrades are always allowed
+    platform(|platform| platform.update_candidate_version = Some(VERSION + 1));
+    assert_eq!(device.call_rebooting(Command::Update, &[]), Rebooted::FirmwareUpdate);
+}
+
+#[test]
+fn expiring_credentials_require_a_credential_ttl() {
+    let mut device = Device::new();
+    platform(|platform| platform.expiring_credentials = Some(4));
+    assert_eq!(device.call(Command::ExpiringCredentials, &u32_bytes(60)), Err(Error::NotAvailable));
+    device.call(Command::SetCredentialTtl, &u32_bytes(86_400)).unwrap();
+    assert_eq!(device.call(Command::ExpiringCredentials, &[60]), Err(Error::InvalidLength));
+    assert_eq!(device.call(Command::ExpiringCredentials, &u32_bytes(60)), Ok(u32_bytes(4)));
+    assert_eq!(platform(|platform| platform.expiry_window), Some(Duration::from_secs(60)));
+}
+
+#[test]
+fn raw_rng_mode_requires_a_trng() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::SetRngMode, &[1]), Err(Error::NotAvailable));
+    assert_eq!(device.call(Command::GetRngMode, &[]), Ok(vec![0]));
+    assert_ne!(device.call(Command::Rng, &[]), Ok(vec![TRNG_BYTE; 57]));
+
+    platform(|platform| platform.trng = true);
+    device.call(Command::SetRngMode, &[1]).unwrap();
+    // Checked without drawing from the TRNG
+    assert_eq!(platform(|platform| platform.trng_draws), 0);
+    assert_eq!(device.call(Command::Rng, &[]), Ok(vec![TRNG_BYTE; 57]));
+    platform(|platform| platform.trng = false);
+    assert_eq!(device.call(Command::Rng, &[]), Err(Error::NotAvailable));
+}
+
+#[test]
+fn rail_voltages() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::RailVoltages, &[0]), Err(Error::NotAvailable));
+    platform(|platform| platform.rail_voltages_mv = &[3300, 1800]);
+    assert_eq!(device.call(Command::RailVoltages, &[]), Err(Error::InvalidLength));
+    assert_eq!(device.call(Command::RailVoltages, &[1]), Ok(1800u16.to_be_bytes().to_vec()));
+    assert_eq!(device.call(Command::RailVoltages, &[2]), Err(Error::UnsupportedCommand));
+}
+
+#[test]
+fn unknown_apdu_instructions_follow_the_policy() {
+    let mut device = Device::new();
+    platform(|platform| platform.rail_voltages_mv = &[3300]);
+    let unknown_id = [0x01];
+    let unknown_rail = extended(Command::RailVoltages, &[5]);
+    assert_eq!(device.apdu(Interface::Contact, 0x01, 0, &[]), Err(Status::InstructionNotSupportedOrInvalid));
+    assert_eq!(device.apdu(Interface::Contact, EXTENDED, 0, &unknown_id), Err(Status::InstructionNotSupportedOrInvalid));
+
+    device.call(Command::SetUnknownInsPolicy, &[1]).unwrap();
+    assert_eq!(device.apdu(Interface::Contact, 0x01, 0, &[]), Err(Status::IncorrectP1OrP2Parameter));
+    assert_eq!(device.apdu(Interface::Contact, EXTENDED, 0, &unknown_id), Err(Status::IncorrectP1OrP2Parameter));
+    // Known commands keep their status
+    assert_eq!(device.apdu(Interface::Contact, EXTENDED, 0, &unknown_rail), Err(Status::InstructionNotSupportedOrInvalid));
+}
+
+#[test]
+fn liveness_advances() {
+    let mut device = Device::new();
+    let first = device.call(Command::Liveness, &[]).unwrap();
+    let second = device.call(Command::Liveness, &[]).unwrap();
+    assert!(u32::from_be_bytes(second.try_into().unwrap()) > u32::from_be_bytes(first.try_into().unwrap()));
+}
+
+#[test]
+fn diagnostics_can_require_presence() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::GetDiagnosticsPolicy, &[]), Ok(vec![0]));
+    grant_presence(Some(0));
+    assert_eq!(device.hid(Command::RecoveryAttemptCount.into(), &[]), Ok(vec![0; 4]));
+
+    device.require_presence_for_diagnostics();
+    assert_eq!(device.call(Command::GetDiagnosticsPolicy, &[]), Ok(vec![1]));
+    let diagnostics = [
+        Command::RecoveryAttemptCount,
+        Command::LastError,
+        Command::SuccessfulUpdateCount,
+        Command::SeCommErrorCount,
+        Command::AttestationSignCount,
+        Command::TotalBytesWritten,
+        Command::LastKeepaliveCount,
+    ];
+    for command in diagnostics {
+        assert_eq!(device.hid(command.into(), &[]), Err(hid::Error::InvalidLength), "{:?}", command);
+    }
+    assert_eq!(device.call(Command::Version, &[]), Ok(u32_bytes(VERSION)));
+    assert_eq!(device.call(Command::ConfigGeneration, &[]), Ok(u32_bytes(0)));
+    grant_presence(None);
+    assert_eq!(device.call(Command::SuccessfulUpdateCount, &[]), Ok(vec![0; 4]));
+}
+
+#[test]
+fn successful_updates_are_counted_after_reboot() {
+    let mut device = Device::new();
+    assert_eq!(device.call(Command::SuccessfulUpdateCount, &[]), Ok(vec![0; 4]));
+    assert_eq!(device.call_rebooting(Command::Update, &[]), Rebooted::FirmwareUpdate);
+    device.boot(VERSION + 1);
+    device.app().check_completed_update();
+    assert_eq!(device.call(Command::SuccessfulUpdateCount, &[]), Ok(u32_bytes(1)));
+    device.boot(VERSION + 1);
+    assert_eq!(device.call(Command::SuccessfulUpdateCount, &[]), Ok(u32_bytes(1)));
+
+    // An update that did not change the version did not complete
+    assert_eq!(device.call_rebooting(Command::Update, &[]), Rebooted::FirmwareUpdate);
+    device.boot(VERSION + 1);
+    assert_eq!(device.call(Command::SuccessfulUpdateCount, &[]), Ok(u32_bytes(1)));
+}
+
+#[test]
+fn expired_certificates_follow_the_expired_cert_policy() {
+    let mut device = Device::new();
+    assert_eq!(device.app().attestation(), Attestation::Full);
+    platform(|platform| platform.attestation_certificate_expired = true);
+    assert_eq!(device.app().attestation(), Attestation::Refused);
+    assert_eq!(device.call(Command::SetExpiredCertPolicy, &[1]), Ok(vec![]));
+    assert_eq!(device.app().attestation(), Attestation::SelfAttestation);
+    device.reboot();
+    assert_eq!(device.app().attestation(), Attestation::SelfAttestation);
+    assert_eq!(device.call(Command::SetExpiredCertPolicy, &[0]), Ok(vec![]));
+    assert_eq!(device.app().attestation(), Attestation::Refused);
+}
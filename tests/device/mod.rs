@@ -0,0 +1,628 @@
+//! A device running the admin app on a RAM-backed Trussed service,
+//! with a mock platform whose state each test configures.
+
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+use admin_app::{App, Command, Error, Reboot};
+use apdu_dispatch::{app as apdu, iso7816::Status};
+use ctaphid_dispatch::app::{self as hid, App as _};
+use ctaphid_dispatch::command::VendorCommand;
+use trussed::types::{Message as TrussedMessage, Signature};
+use trussed::Interchange as _;
+
+pub const UUID: [u8; 16] = [0x55; 16];
+pub const VERSION: u32 = 7;
+pub const EXTENDED: VendorCommand = VendorCommand::H64;
+
+/// Bytes the mock TRNG fills buffers with.
+pub const TRNG_BYTE: u8 = 0xA5;
+/// Signature the mock attestation key makes over any message.
+pub const SIGNATURE: [u8; 8] = [0x51; 8];
+
+// The store macro generates an eight-argument `allocate`.
+#[allow(clippy::too_many_arguments)]
+mod store {
+    use littlefs2::const_ram_storage;
+    use trussed::types::{LfsResult, LfsStorage};
+
+    const_ram_storage!(InternalStorage, 128 * 1024);
+    const_ram_storage!(ExternalStorage, 16 * 1024);
+    const_ram_storage!(VolatileStorage, 16 * 1024);
+
+    trussed::store!(Store,
+        Internal: InternalStorage,
+        External: ExternalStorage,
+        Volatile: VolatileStorage
+    );
+}
+
+mod ui {
+    use trussed::platform::consent;
+
+    use super::{platform, MOCK};
+
+    pub struct UserInterface;
+
+    impl trussed::platform::UserInterface for UserInterface {
+        fn check_user_presence(&mut self) -> consent::Level {
+            MOCK.with(|mock| mock.prompts.set(mock.prompts.get() + 1));
+            MOCK.with(|mock| match mock.grants.get() {
+                None => consent::Level::Normal,
+                Some(0) => consent::Level::None,
+                Some(grants) => {
+                    mock.grants.set(Some(grants - 1));
+                    consent::Level::Normal
+                }
+            })
+        }
+
+        /// Advances by a second on every call, so that presence
+        /// prompts time out and the uptime is seen to advance.
+        fn uptime(&mut self) -> core::time::Duration {
+            MOCK.with(|mock| {
+                let uptime = mock.uptime.get() + core::time::Duration::from_secs(1);
+                mock.uptime.set(uptime);
+                uptime
+            })
+        }
+
+        fn wink(&mut self, _duration: core::time::Duration) {
+            platform(|platform| platform.winked = true);
+        }
+    }
+}
+
+pub struct XorShiftRng(u64);
+
+impl rand_core::RngCore for XorShiftRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // xorshift64, the tests need no real randomness
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl rand_core::CryptoRng for XorShiftRng {}
+
+trussed::platform!(TrussedPlatform,
+    R: XorShiftRng,
+    S: store::Store,
+    UI: ui::UserInterface,
+);
+
+type Client = trussed::ClientImplementation<&'static mut trussed::Service<TrussedPlatform>>;
+
+/// How the mock platform was asked to reboot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rebooted {
+    Normal,
+    FirmwareUpdate,
+    FirmwareUpdateDestructive,
+    Recovery,
+}
+
+/// What the mock platform reports through the [`Reboot`] hooks.
+#[derive(Default)]
+pub struct Platform {
+    pub locked: bool,
+    pub rng_reseed_info: Option<(Duration, Duration)>,
+    pub attestation_key: bool,
+    pub attestation_certificate: Option<&'static [u8]>,
+    pub update_progress: Option<u8>,
+    pub fingerprint_capacity: Option<u8>,
+    pub enrolled_fingerprints: Option<u8>,
+    pub secure_boot_rollback_index: Option<u32>,
+    pub power_consumption_mw: Option<u32>,
+    pub heap_fragmentation: Option<u8>,
+    pub display: bool,
+    pub diagnostics_requested: bool,
+    pub winked: bool,
+    pub attestation_sign_count: Option<u32>,
+    pub last_verify_time_ms: Option<u32>,
+    pub touch_sensitivity: Option<u8>,
+    pub presence_hardware_failed: bool,
+    pub alternate_presence: bool,
+    pub large_blob_capacity: Option<u32>,
+    pub large_blob_usage: Option<u32>,
+    pub app_ids: &'static [u8],
+    pub disabled_apps: &'static [u8],
+    pub signing: bool,
+    pub corrupt_credentials: Option<u16>,
+    pub queued_presence_prompts: u8,
+    pub rollback_fuse_state: Option<u32>,
+    pub debug_port_locked: bool,
+    pub max_image_size: Option<u32>,
+    pub crypto_ops_per_sec: Option<u32>,
+    pub uptime_since_crash_ms: Option<u64>,
+    pub se_comm_errors: Option<u32>,
+    pub wear_leveling_status: Option<u8>,
+    pub max_interrupt_latency_us: Option<u32>,
+    pub free_counter_slots: Option<u32>,
+    pub dispatch_lock_state: Option<(u8, bool)>,
+    pub bootloader_protocol_version: Option<u16>,
+    pub certificate_capacity: Option<u8>,
+    pub last_self_test: Option<Duration>,
+    pub entropy_pool_percent: Option<u8>,
+    pub dispatch_stack_usage: Option<u32>,
+    pub peak_command_alloc: Option<u32>,
+    pub usb_descriptor_config: Option<(u16, u16, u8)>,
+    pub scheduler_drift_ppm: Option<i32>,
+    pub firmware_crc32: Option<u32>,
+    pub expiring_credentials: Option<u32>,
+    pub expiry_window: Option<Duration>,
+    pub trng: bool,
+    pub trng_draws: u32,
+    pub dispatcher_version: Option<u32>,
+    pub rail_voltages_mv: &'static [u16],
+    pub last_keepalive_count: Option<u32>,
+    pub total_flash_bytes_written: Option<u64>,
+    pub signed_message: Option<Vec<u8>>,
+    pub rng_unhealthy: bool,
+    pub reseed_repairs_rng: bool,
+    pub rng_reseeds: u32,
+    pub signature_counts: &'static [(u8, u64)],
+    pub update_candidate_version: Option<u32>,
+    pub attestation_certificate_expired: bool,
+    pub rebooted: Option<Rebooted>,
+}
+
+struct Mock {
+    platform: RefCell<Platform>,
+    /// Presence prompts the user confirms, all of them if `None`.
+    grants: Cell<Option<u32>>,
+    /// Times the user was asked to confirm their presence.
+    prompts: Cell<u32>,
+    uptime: Cell<core::time::Duration>,
+    /// Whether the mock panic hook saw a panic.
+    panicked: Cell<bool>,
+}
+
+thread_local! {
+    static MOCK: Mock = Mock {
+        platform: RefCell::new(Platform::default()),
+        grants: Cell::new(None),
+        prompts: Cell::new(0),
+        uptime: Cell::new(core::time::Duration::ZERO),
+        panicked: Cell::new(false),
+    };
+}
+
+/// Reads or changes the state of the mock platform.
+pub fn platform<T>(f: impl FnOnce(&mut Platform) -> T) -> T {
+    MOCK.with(|mock| f(&mut mock.platform.borrow_mut()))
+}
+
+fn reboot_to(to: Rebooted) -> ! {
+    platform(|platform| platform.rebooted = Some(to));
+    panic!("rebooted");
+}
+
+pub struct Board;
+
+impl Reboot for Board {
+    fn reboot() -> ! {
+        reboot_to(Rebooted::Normal)
+    }
+
+    fn reboot_to_firmware_update() -> ! {
+        reboot_to(Rebooted::FirmwareUpdate)
+    }
+
+    fn reboot_to_firmware_update_destructive() -> ! {
+        reboot_to(Rebooted::FirmwareUpdateDestructive)
+    }
+
+    fn reboot_to_recovery() -> ! {
+        reboot_to(Rebooted::Recovery)
+    }
+
+    fn locked() -> bool {
+        platform(|platform| platform.locked)
+    }
+
+    fn rng_reseed_info() -> Option<(Duration, Duration)> {
+        platform(|platform| platform.rng_reseed_info)
+    }
+
+    fn attestation_key_provisioned() -> bool {
+        platform(|platform| platform.attestation_key)
+    }
+
+    fn attestation_certificate() -> Option<TrussedMessage> {
+        platform(|platform| platform.attestation_certificate)
+            .map(|certificate| TrussedMessage::from_slice(certificate).unwrap())
+    }
+
+    fn update_progress() -> Option<u8> {
+        platform(|platform| platform.update_progress)
+    }
+
+    fn fingerprint_capacity() -> Option<u8> {
+        platform(|platform| platform.fingerprint_capacity)
+    }
+
+    fn enrolled_fingerprints() -> Option<u8> {
+        platform(|platform| platform.enrolled_fingerprints)
+    }
+
+    fn secure_boot_rollback_index() -> Option<u32> {
+        platform(|platform| platform.secure_boot_rollback_index)
+    }
+
+    fn power_consumption_mw() -> Option<u32> {
+        platform(|platform| platform.power_consumption_mw)
+    }
+
+    fn heap_fragmentation() -> Option<u8> {
+        platform(|platform| platform.heap_fragmentation)
+    }
+
+    fn show_diagnostics() -> bool {
+        platform(|platform| {
+            platform.diagnostics_requested = true;
+            platform.display
+        })
+    }
+
+    fn attestation_sign_count() -> Option<u32> {
+        platform(|platform| platform.attestation_sign_count)
+    }
+
+    fn last_verify_time_ms() -> Option<u32> {
+        platform(|platform| platform.last_verify_time_ms)
+    }
+
+    fn touch_sensitivity() -> Option<u8> {
+        platform(|platform| platform.touch_sensitivity)
+    }
+
+    fn set_touch_sensitivity(sensitivity: u8) -> bool {
+        platform(|platform| match platform.touch_sensitivity {
+            Some(_) => {
+                platform.touch_sensitivity = Some(sensitivity);
+                true
+            }
+            None => false,
+        })
+    }
+
+    fn presence_hardware_failed() -> bool {
+        platform(|platform| platform.presence_hardware_failed)
+    }
+
+    fn confirm_presence_alternately() -> bool {
+        platform(|platform| platform.alternate_presence)
+    }
+
+    fn large_blob_capacity() -> Option<u32> {
+        platform(|platform| platform.large_blob_capacity)
+    }
+
+    fn large_blob_usage() -> Option<u32> {
+        platform(|platform| platform.large_blob_usage)
+    }
+
+    fn app_ids() -> &'static [u8] {
+        platform(|platform| platform.app_ids)
+    }
+
+    fn app_enabled(id: u8) -> bool {
+        platform(|platform| !platform.disabled_apps.contains(&id))
+    }
+
+    fn can_sign_with_attestation_key() -> bool {
+        platform(|platform| platform.signing)
+    }
+
+    fn sign_with_attestation_key(message: &[u8]) -> Option<Signature> {
+        platform(|platform| {
+            platform.signed_message = Some(message.to_vec());
+            platform.signing.then(|| Signature::from_slice(&SIGNATURE).unwrap())
+        })
+    }
+
+    fn corrupt_credentials(_app_id: u8) -> Option<u16> {
+        platform(|platform| platform.corrupt_credentials)
+    }
+
+    fn queued_presence_prompts() -> u8 {
+        platform(|platform| platform.queued_presence_prompts)
+    }
+
+    fn rollback_fuse_state() -> Option<u32> {
+        platform(|platform| platform.rollback_fuse_state)
+    }
+
+    fn debug_port_locked() -> bool {
+        platform(|platform| platform.debug_port_locked)
+    }
+
+    fn max_image_size() -> Option<u32> {
+        platform(|platform| platform.max_image_size)
+    }
+
+    fn crypto_ops_per_sec() -> Option<u32> {
+        platform(|platform| platform.crypto_ops_per_sec)
+    }
+
+    fn uptime_since_crash_ms() -> Option<u64> {
+        platform(|platform| platform.uptime_since_crash_ms)
+    }
+
+    fn se_comm_errors() -> Option<u32> {
+        platform(|platform| platform.se_comm_errors)
+    }
+
+    fn wear_leveling_status() -> Option<u8> {
+        platform(|platform| platform.wear_leveling_status)
+    }
+
+    fn max_interrupt_latency_us() -> Option<u32> {
+        platform(|platform| platform.max_interrupt_latency_us)
+    }
+
+    fn free_counter_slots() -> Option<u32> {
+        platform(|platform| platform.free_counter_slots)
+    }
+
+    fn dispatch_lock_state() -> Option<(u8, bool)> {
+        platform(|platform| platform.dispatch_lock_state)
+    }
+
+    fn bootloader_protocol_version() -> Option<u16> {
+        platform(|platform| platform.bootloader_protocol_version)
+    }
+
+    fn certificate_capacity() -> Option<u8> {
+        platform(|platform| platform.certificate_capacity)
+    }
+
+    fn last_self_test() -> Option<Duration> {
+        platform(|platform| platform.last_self_test)
+    }
+
+    fn entropy_pool_percent() -> Option<u8> {
+        platform(|platform| platform.entropy_pool_percent)
+    }
+
+    fn dispatch_stack_usage() -> Option<u32> {
+        platform(|platform| platform.dispatch_stack_usage)
+    }
+
+    fn peak_command_alloc() -> Option<u32> {
+        platform(|platform| platform.peak_command_alloc)
+    }
+
+    fn usb_descriptor_config() -> Option<(u16, u16, u8)> {
+        platform(|platform| platform.usb_descriptor_config)
+    }
+
+    fn scheduler_drift_ppm() -> Option<i32> {
+        platform(|platform| platform.scheduler_drift_ppm)
+    }
+
+    fn firmware_crc32() -> Option<u32> {
+        platform(|platform| platform.firmware_crc32)
+    }
+
+    fn credentials_expiring_within(window: Duration) -> Option<u32> {
+        platform(|platform| {
+            platform.expiry_window = Some(window);
+            platform.expiring_credentials
+        })
+    }
+
+    fn has_trng() -> bool {
+        platform(|platform| platform.trng)
+    }
+
+    fn trng_bytes(buffer: &mut [u8]) -> bool {
+        buffer.fill(TRNG_BYTE);
+        platform(|platform| {
+            platform.trng_draws += 1;
+            platform.trng
+        })
+    }
+
+    fn dispatcher_version() -> Option<u32> {
+        platform(|platform| platform.dispatcher_version)
+    }
+
+    fn rail_count() -> u8 {
+        platform(|platform| platform.rail_voltages_mv.len() as u8)
+    }
+
+    fn rail_voltage_mv(rail: u8) -> Option<u16> {
+        platform(|platform| platform.rail_voltages_mv.get(rail as usize).copied())
+    }
+
+    fn last_keepalive_count() -> Option<u32> {
+        platform(|platform| platform.last_keepalive_count)
+    }
+
+    fn total_flash_bytes_written() -> Option<u64> {
+        platform(|platform| platform.total_flash_bytes_written)
+    }
+
+    fn rng_healthy() -> bool {
+        platform(|platform| !platform.rng_unhealthy)
+    }
+
+    fn reseed_rng() -> bool {
+        platform(|platform| {
+            platform.rng_reseeds += 1;
+            platform.rng_unhealthy &= !platform.reseed_repairs_rng;
+            !platform.rng_unhealthy
+        })
+    }
+
+    fn signature_count(id: u8) -> Option<u64> {
+        platform(|platform| {
+            platform.signature_counts.iter().find(|(app, _)| *app == id).map(|(_, count)| *count)
+        })
+    }
+
+    fn update_candidate_version() -> Option<u32> {
+        platform(|platform| platform.update_candidate_version)
+    }
+
+    fn attestation_certificate_expired() -> bool {
+        platform(|platform| platform.attestation_certificate_expired)
+    }
+}
+
+/// The store can only be claimed once per process.
+struct Claimed(store::Store);
+
+// The tests take turns on the store, see `Device::new`.
+unsafe impl Send for Claimed {}
+unsafe impl Sync for Claimed {}
+
+static TURN: Mutex<()> = Mutex::new(());
+
+pub struct Device {
+    app: Option<App<Client, Board>>,
+    store: store::Store,
+    _turn: MutexGuard<'static, ()>,
+}
+
+impl Device {
+    /// Boots a device with blank storage, and a platform without
+    /// any optional capability.
+    pub fn new() -> Self {
+        let turn = TURN.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        static STORE: OnceLock<Claimed> = OnceLock::new();
+        let store = STORE.get_or_init(|| Claimed(store::Store::claim().unwrap())).0;
+        let (ifs_alloc, ifs_storage, efs_alloc, efs_storage, vfs_alloc, vfs_storage) = store::Store::allocate(
+            store::InternalStorage::new(),
+            store::ExternalStorage::new(),
+            store::VolatileStorage::new(),
+        );
+        store.mount(ifs_alloc, ifs_storage, efs_alloc, efs_storage, vfs_alloc, vfs_storage, true).unwrap();
+        platform(|platform| *platform = Platform::default());
+        grant_presence(None);
+        let mut device = Self { app: None, store, _turn: turn };
+        device.boot(VERSION);
+        device
+    }
+
+    /// Simulates a reboot into the given firmware version, keeping
+    /// the persistent storage.
+    pub fn boot(&mut self, version: u32) {
+        self.app = None;
+        unsafe { trussed::pipe::TrussedInterchange::reset_claims() };
+        let platform = TrussedPlatform::new(XorShiftRng(0x2545_F491_4F6C_DD1D), self.store, ui::UserInterface);
+        let service = Box::leak(Box::new(trussed::Service::new(platform)));
+        service.set_seed_if_uninitialized(&[0; 32]);
+        let client = service.try_as_new_client("admin").unwrap();
+        self.app = Some(App::new(client, UUID, version));
+    }
+
+    pub fn reboot(&mut self) {
+        self.boot(VERSION);
+    }
+
+    pub fn app(&mut self) -> &mut App<Client, Board> {
+        self.app.as_mut().unwrap()
+    }
+
+    pub fn require_presence_for_diagnostics(&mut self) {
+        let app = self.app.take().unwrap();
+        self.app = Some(app.require_presence_for_diagnostics());
+    }
+
+    /// Sends the command with the given id over CTAPHID.
+    pub fn hid(&mut self, id: u8, data: &[u8]) -> Result<Vec<u8>, hid::Error> {
+        let mut request = hid::Message::new();
+        request.push(id).unwrap();
+        request.extend_from_slice(data).unwrap();
+        let mut response = hid::Message::new();
+        self.app().call(hid::Command::Vendor(EXTENDED), &request, &mut response)?;
+        Ok(response.to_vec())
+    }
+
+    /// Sends the command over the contact interface, recovering the
+    /// precise error from the status word.
+    pub fn call(&mut self, command: Command, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut request = vec![command.into()];
+        request.extend_from_slice(data);
+        self.apdu(apdu::Interface::Contact, EXTENDED as u8, 0, &request).map_err(|status| match status {
+            Status::WrongLength => Error::InvalidLength,
+            Status::IncorrectDataParameter => Error::InvalidValue,
+            Status::ConditionsOfUseNotSatisfied => Error::NotAvailable,
+            Status::InstructionNotSupportedOrInvalid => Error::UnsupportedCommand,
+            status => panic!("unexpected status {:?}", status),
+        })
+    }
+
+    /// Sends an APDU with the given instruction, with an extended
+    /// length if the data does not fit a short one.
+    pub fn apdu(&mut self, interface: apdu::Interface, instruction: u8, p1: u8, data: &[u8]) -> Result<Vec<u8>, Status> {
+        let mut bytes = vec![0x00, instruction, p1, 0x00];
+        match data.len() {
+            0 => {}
+            len @ 1..=255 => bytes.push(len as u8),
+            len => bytes.extend_from_slice(&[0x00, (len >> 8) as u8, len as u8]),
+        }
+        bytes.extend_from_slice(data);
+        let command = apdu_dispatch::Command::try_from(&bytes[..]).unwrap();
+        let mut reply = apdu_dispatch::response::Data::new();
+        apdu::App::call(self.app(), interface, &command, &mut reply)?;
+        Ok(reply.to_vec())
+    }
+
+    /// Sends a command expected to reboot the device,
+    /// returning how the platform was asked to reboot.
+    pub fn call_rebooting(&mut self, command: Command, data: &[u8]) -> Rebooted {
+        platform(|platform| platform.rebooted = None);
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.hid(command.into(), data)));
+        panic::set_hook(hook);
+        assert!(result.is_err(), "{:?} returned without rebooting", command);
+        platform(|platform| platform.rebooted.take()).unwrap()
+    }
+
+    /// Panics, then handles the panic like the runner's panic handler,
+    /// returning how the platform was asked to reboot.
+    pub fn panic(&mut self) -> Rebooted {
+        platform(|platform| platform.rebooted = None);
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| MOCK.with(|mock| mock.panicked.set(true))));
+        panic::catch_unwind(|| panic!("command failed")).unwrap_err();
+        assert!(MOCK.with(|mock| mock.panicked.take()));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.app().handle_panic()));
+        panic::set_hook(hook);
+        assert!(result.is_err(), "the panic handler returned without rebooting");
+        platform(|platform| platform.rebooted.take()).unwrap()
+    }
+}
+
+/// Lets the user confirm the given number of presence prompts,
+/// or all of them if `None`.
+pub fn grant_presence(grants: Option<u32>) {
+    MOCK.with(|mock| mock.grants.set(grants));
+}
+
+/// Times the user was asked to confirm their presence so far.
+pub fn presence_prompts() -> u32 {
+    MOCK.with(|mock| mock.prompts.get())
+}